@@ -0,0 +1,12 @@
+use std::{env, path::PathBuf};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let out_dir = PathBuf::from(env::var("OUT_DIR")?);
+
+    tonic_build::configure()
+        .build_client(false)
+        .file_descriptor_set_path(out_dir.join("llm_gateway_descriptor.bin"))
+        .compile_protos(&["proto/llm_gateway.proto"], &["proto"])?;
+
+    Ok(())
+}
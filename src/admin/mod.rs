@@ -0,0 +1,197 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{
+    extract::{Path, Query, Request, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    middleware::{self, Next},
+    response::Response,
+    routing::{get, post, put},
+    Json, Router,
+};
+use secrecy::{ExposeSecret, Secret};
+use serde::{Deserialize, Serialize};
+
+use crate::config::reload::ConfigStatus;
+use crate::llm::pool::{GatewayStatus, ProviderStatus, ProviderUpdate};
+use crate::llm::provider::LlmProvider;
+use crate::llm::usage::UsageRecord;
+
+// Дольше ждать завершения запросов в одном HTTP-запросе нельзя
+const MAX_DRAIN_WAIT: Duration = Duration::from_secs(300);
+
+#[derive(Deserialize)]
+struct DrainQuery {
+    // Сколько секунд ждать, пока у провайдера не останется запросов в работе.
+    // Без параметра ответ возвращается сразу, и дождаться нуля можно, опрашивая /admin/providers
+    #[serde(default)]
+    wait: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct StatusQuery {
+    // Каждый провайдер дополнительно проверяется запросом списка моделей
+    #[serde(default)]
+    ping: bool,
+}
+
+#[derive(Serialize)]
+struct DrainResponse {
+    name: String,
+    draining: bool,
+    inflight: usize,
+}
+
+// Административный API, все запросы требуют заголовок `Authorization: Bearer <ADMIN_TOKEN>`
+pub fn router(token: Secret<String>) -> Router<Arc<LlmProvider>> {
+    Router::new()
+        .route("/status", get(handle_status))
+        .route("/providers", get(handle_list_providers))
+        .route("/providers/{name}", put(handle_put_provider).delete(handle_remove_provider))
+        .route("/providers/{name}/drain", post(handle_drain).delete(handle_resume))
+        .route("/reload", post(handle_reload))
+        .route("/requests/{id}/cancel", post(handle_cancel))
+        .route("/usage", get(handle_usage))
+        .route("/experiments/{name}/export", get(handle_export_experiment))
+        .layer(middleware::from_fn_with_state(Arc::new(token), authorize))
+}
+
+async fn authorize(
+    State(token): State<Arc<Secret<String>>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let provided = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or_default();
+
+    if !constant_time_eq(provided.as_bytes(), token.expose_secret().as_bytes()) {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    Ok(next.run(request).await)
+}
+
+// Время сравнения не зависит от того, в каком байте токены расходятся
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+async fn handle_status(
+    State(service): State<Arc<LlmProvider>>,
+    Query(query): Query<StatusQuery>,
+) -> Json<GatewayStatus> {
+    Json(service.status(query.ping).await)
+}
+
+async fn handle_list_providers(
+    State(service): State<Arc<LlmProvider>>,
+) -> Json<Vec<ProviderStatus>> {
+    Json(service.provider_statuses(false).await)
+}
+
+async fn handle_put_provider(
+    State(service): State<Arc<LlmProvider>>,
+    Path(name): Path<String>,
+    Json(update): Json<ProviderUpdate>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let replaced = service
+        .put_provider(&name, update).await
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+
+    Ok(if replaced { StatusCode::OK } else { StatusCode::CREATED })
+}
+
+async fn handle_remove_provider(
+    State(service): State<Arc<LlmProvider>>,
+    Path(name): Path<String>,
+) -> StatusCode {
+    if service.remove_provider(&name) {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+async fn handle_drain(
+    State(service): State<Arc<LlmProvider>>,
+    Path(name): Path<String>,
+    Query(query): Query<DrainQuery>,
+) -> Result<Json<DrainResponse>, StatusCode> {
+    let mut response = set_draining(&service, name, true)?;
+
+    if let Some(wait) = query.wait.filter(|_| response.inflight > 0) {
+        let wait = Duration::from_secs(wait).min(MAX_DRAIN_WAIT);
+        let _ = tokio::time::timeout(wait, service.wait_drained(&response.name)).await;
+        response.inflight = service.inflight(&response.name);
+    }
+
+    Ok(response)
+}
+
+async fn handle_resume(
+    State(service): State<Arc<LlmProvider>>,
+    Path(name): Path<String>,
+) -> Result<Json<DrainResponse>, StatusCode> {
+    set_draining(&service, name, false)
+}
+
+fn set_draining(service: &LlmProvider, name: String, draining: bool) -> Result<Json<DrainResponse>, StatusCode> {
+    let inflight = service
+        .drain_provider(&name, draining)
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    Ok(Json(DrainResponse { name: name.to_uppercase(), draining, inflight }))
+}
+
+async fn handle_cancel(
+    State(service): State<Arc<LlmProvider>>,
+    Path(id): Path<String>,
+) -> StatusCode {
+    if service.cancel(&id) {
+        StatusCode::ACCEPTED
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+async fn handle_usage(
+    State(service): State<Arc<LlmProvider>>,
+) -> Json<Vec<UsageRecord>> {
+    Json(service.usage())
+}
+
+async fn handle_reload(
+    State(service): State<Arc<LlmProvider>>,
+) -> Result<Json<ConfigStatus>, (StatusCode, String)> {
+    service
+        .reload_config(true).await
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+
+    Ok(Json(service.config_status()))
+}
+
+// Пары ответов построчным JSON для офлайн-сравнения; в них полные диалоги клиентов
+async fn handle_export_experiment(
+    State(service): State<Arc<LlmProvider>>,
+    Path(name): Path<String>,
+) -> Result<(HeaderMap, String), (StatusCode, String)> {
+    let records = service
+        .export_experiment(&name)
+        .map_err(|e| (StatusCode::NOT_FOUND, e.to_string()))?;
+
+    let mut body = String::new();
+    for record in records {
+        let line = serde_json::to_string(&record)
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        body.push_str(&line);
+        body.push('\n');
+    }
+
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/x-ndjson"));
+    Ok((headers, body))
+}
@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::llm::catalog::ModelCapabilities;
+use crate::llm::experiments::ExperimentConfig;
+use crate::llm::router::RouteConfig;
+use crate::llm::SamplingParams;
+
+/// Необязательный TOML-файл с настройками, которые неудобно задавать переменными окружения.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct GatewayConfig {
+    #[serde(default)]
+    pub models: Vec<ModelOverride>,
+    #[serde(default)]
+    pub aliases: HashMap<String, ModelAlias>,
+    #[serde(default)]
+    pub routes: HashMap<String, RouteConfig>,
+    #[serde(default)]
+    pub experiments: HashMap<String, ExperimentConfig>,
+}
+
+/// Имя модели для клиентов, за которым стоит конкретный провайдер и модель.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ModelAlias {
+    #[serde(flatten)]
+    pub target: AliasTarget,
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    #[serde(flatten)]
+    pub sampling: SamplingParams,
+    // Цели, которые пробуются по порядку, если основная вернула ошибку
+    #[serde(default)]
+    pub fallback: Vec<AliasTarget>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AliasTarget {
+    pub provider: String,
+    pub model: String,
+}
+
+/// Уточнение или дополнение сведений о модели из каталога провайдера.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ModelOverride {
+    pub provider: String,
+    pub id: String,
+    #[serde(default)]
+    pub hidden: bool,
+    #[serde(flatten)]
+    pub capabilities: ModelCapabilities,
+}
+
+impl AliasTarget {
+    fn check(&self, owner: &str, errors: &mut Vec<String>) {
+        if self.provider.is_empty() || self.model.is_empty() {
+            errors.push(format!("{}: не указаны provider и model", owner));
+        }
+    }
+}
+
+impl GatewayConfig {
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let config: Self = toml::from_str(text)?;
+
+        let errors = config.validate();
+        if !errors.is_empty() {
+            return Err(anyhow::anyhow!("Некорректная конфигурация: {}", errors.join("; ")));
+        }
+
+        Ok(config)
+    }
+
+    // Ошибки, которые не ловит разбор TOML
+    fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        for model in &self.models {
+            if model.provider.is_empty() || model.id.is_empty() {
+                errors.push("models: не указаны provider и id".to_string());
+            }
+            if let Some(pricing) = &model.capabilities.pricing {
+                if pricing.input < 0.0 || pricing.output < 0.0 {
+                    errors.push(format!("models.{}: отрицательная цена", model.id));
+                }
+            }
+        }
+
+        for (name, alias) in &self.aliases {
+            alias.target.check(&format!("aliases.{}", name), &mut errors);
+            for target in &alias.fallback {
+                target.check(&format!("aliases.{}.fallback", name), &mut errors);
+            }
+            if self.routes.contains_key(name) {
+                errors.push(format!("{}: имя используется и алиасом, и маршрутом", name));
+            }
+        }
+
+        for (name, route) in &self.routes {
+            if route.targets.is_empty() {
+                errors.push(format!("routes.{}: не указаны цели", name));
+            }
+            for target in &route.targets {
+                target.target.check(&format!("routes.{}", name), &mut errors);
+                if !target.weight.is_finite() || target.weight < 0.0 {
+                    errors.push(format!("routes.{}: вес должен быть неотрицательным числом", name));
+                }
+            }
+        }
+
+        for (name, experiment) in &self.experiments {
+            if experiment.model.is_empty() {
+                errors.push(format!("experiments.{}: не указана model", name));
+            }
+            experiment.candidate.check(&format!("experiments.{}.candidate", name), &mut errors);
+            if !(0.0..=100.0).contains(&experiment.percent) {
+                errors.push(format!("experiments.{}: percent должен быть от 0 до 100", name));
+            }
+        }
+
+        errors.sort();
+        errors
+    }
+}
@@ -1,4 +1,9 @@
+pub mod file;
+pub mod reload;
+
 use std::env;
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use secrecy::Secret;
 use serde::Deserialize;
 
@@ -13,7 +18,52 @@ pub enum Model {
 #[derive(Debug, Clone, Deserialize)]
 pub struct ModelData {
     pub token: Secret<String>,
-    pub scope: Option<String>
+    pub scope: Option<String>,
+    #[serde(default)]
+    pub network: NetworkSettings,
+}
+
+// Префиксы переменных окружения провайдера, к ним добавляется имя провайдера: `TOKEN_GIGACHAT`
+pub const PROVIDER_ENV: [&str; 7] = ["TOKEN", "SCOPE", "CA_CERTS", "TLS_PINS", "CLIENT_CERT", "CLIENT_KEY", "PROXY"];
+
+impl ModelData {
+    // Без токена провайдер не настроен
+    pub fn from_env(name: &str, var: impl Fn(&str) -> Option<String>) -> Option<Self> {
+        let var = |prefix: &str| var(&format!("{}_{}", prefix, name)).filter(|value| !value.is_empty());
+        let list = |prefix: &str| -> Vec<String> {
+            var(prefix)
+                .map(|value| value.split(',').map(|item| item.trim().to_string()).filter(|item| !item.is_empty()).collect())
+                .unwrap_or_default()
+        };
+
+        Some(Self {
+            token: Secret::new(var("TOKEN")?),
+            scope: var("SCOPE"),
+            network: NetworkSettings {
+                ca_certs: list("CA_CERTS").into_iter().map(PathBuf::from).collect(),
+                pinned_certs: list("TLS_PINS"),
+                client_cert: var("CLIENT_CERT").map(PathBuf::from),
+                client_key: var("CLIENT_KEY").map(PathBuf::from),
+                proxy: var("PROXY"),
+            },
+        })
+    }
+}
+
+// Сетевые настройки провайдера, общие для получения токена и запросов к API
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct NetworkSettings {
+    // Дополнительные корневые сертификаты в PEM, например сертификаты Минцифры для GigaChat
+    #[serde(default)]
+    pub ca_certs: Vec<PathBuf>,
+    // SHA-256 отпечатки допустимых сертификатов сервера в hex, двоеточия необязательны
+    #[serde(default)]
+    pub pinned_certs: Vec<String>,
+    // Клиентский сертификат и ключ в PEM, ключ в формате PKCS#8
+    pub client_cert: Option<PathBuf>,
+    pub client_key: Option<PathBuf>,
+    // http://, https:// или socks5://, с учётными данными в адресе при необходимости
+    pub proxy: Option<String>,
 }
 
 macro_rules! create_service {
@@ -44,6 +94,20 @@ impl Model {
         }
     }
 
+    pub fn all() -> Vec<Model> {
+        vec![Model::GigaChat(None), Model::DeepSeek(None)]
+    }
+
+    pub fn from_kind(kind: &str, data: ModelData) -> Option<Self> {
+        let mut model = match kind.to_lowercase().as_str() {
+            "gigachat" => Model::GigaChat(None),
+            "deepseek" => Model::DeepSeek(None),
+            _ => return None,
+        };
+        model.set_data(data);
+        Some(model)
+    }
+
     pub fn set_data(&mut self, data: ModelData) {
         match self {
             Model::GigaChat(_) => *self = Model::GigaChat(Some(data)),
@@ -53,27 +117,234 @@ impl Model {
 }
 
 pub fn load() -> Vec<Model> {
-    let mut models = vec![
-        Model::GigaChat(None), Model::DeepSeek(None)
-    ];
+    let mut models = Model::all();
     dotenvy::dotenv().ok();
     
     for model in &mut models {
         let model_name = model.to_string().to_uppercase();
 
-        if let Ok(token) = env::var(
-            format!("TOKEN_{}", model_name)
-        ) {
-            let data = ModelData {
-                token: Secret::new(token),
-                scope: match env::var(format!("SCOPE_{}", model_name)) {
-                    Ok(scope) => Some(scope),
-                    _ => None
-                },
-            };
+        if let Some(data) = ModelData::from_env(&model_name, |name| env::var(name).ok()) {
             model.set_data(data);
         }
     }
 
     models
 }
+
+#[derive(Debug, Clone)]
+pub struct SemanticCacheConfig {
+    pub provider: String,
+    pub model: String,
+    pub threshold: f32,
+    pub capacity: usize
+}
+
+pub fn load_semantic_cache() -> Option<SemanticCacheConfig> {
+    dotenvy::dotenv().ok();
+
+    let provider = env::var("SEMANTIC_CACHE_PROVIDER").ok()?;
+    let model = env::var("SEMANTIC_CACHE_MODEL").ok()?;
+
+    Some(SemanticCacheConfig {
+        provider,
+        model,
+        threshold: env::var("SEMANTIC_CACHE_THRESHOLD")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(0.95),
+        capacity: env::var("SEMANTIC_CACHE_SIZE")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(1000),
+    })
+}
+
+pub fn store_path() -> PathBuf {
+    dotenvy::dotenv().ok();
+
+    match env::var("STORE_PATH") {
+        Ok(path) => PathBuf::from(path),
+        Err(_) => {
+            println!("STORE_PATH переменная окружения не установлена. Используется значение по умолчанию.");
+            PathBuf::from("store")
+        }
+    }
+}
+
+pub fn rag_template() -> String {
+    dotenvy::dotenv().ok();
+
+    rag_template_from(|name| env::var(name).ok())
+}
+
+pub fn rag_template_from(var: impl Fn(&str) -> Option<String>) -> String {
+    var("RAG_TEMPLATE").unwrap_or_else(|| {
+        "Ответь на вопрос, используя только приведённый контекст.\n\n\
+        Контекст:\n{context}\n\nВопрос: {question}".to_string()
+    })
+}
+
+pub fn grpc_address() -> anyhow::Result<SocketAddr> {
+    dotenvy::dotenv().ok();
+
+    Ok(env::var("GRPC_ADDR")
+        .unwrap_or_else(|_| "0.0.0.0:50051".to_string())
+        .parse()?)
+}
+
+pub fn usage_log_path() -> Option<PathBuf> {
+    dotenvy::dotenv().ok();
+
+    env::var("USAGE_LOG_PATH").ok().map(PathBuf::from)
+}
+
+pub fn prompts_path() -> PathBuf {
+    dotenvy::dotenv().ok();
+
+    match env::var("PROMPTS_PATH") {
+        Ok(path) => PathBuf::from(path),
+        Err(_) => {
+            println!("PROMPTS_PATH переменная окружения не установлена. Используется значение по умолчанию.");
+            PathBuf::from("prompts")
+        }
+    }
+}
+
+pub fn params_strict() -> bool {
+    dotenvy::dotenv().ok();
+
+    params_strict_from(|name| env::var(name).ok())
+}
+
+pub fn params_strict_from(var: impl Fn(&str) -> Option<String>) -> bool {
+    var("PARAMS_STRICT")
+        .map(|value| matches!(value.to_lowercase().as_str(), "1" | "true" | "yes"))
+        .unwrap_or(false)
+}
+
+pub fn attachment_ttl() -> chrono::Duration {
+    dotenvy::dotenv().ok();
+
+    chrono::Duration::seconds(env::var("ATTACHMENT_TTL")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(3600))
+}
+
+// Внешний адрес шлюза для ссылок на вложения, без него ссылки относительные
+pub fn public_url() -> String {
+    dotenvy::dotenv().ok();
+
+    env::var("PUBLIC_URL")
+        .map(|url| url.trim_end_matches('/').to_string())
+        .unwrap_or_default()
+}
+
+pub fn config_path() -> PathBuf {
+    dotenvy::dotenv().ok();
+
+    PathBuf::from(env::var("CONFIG_PATH").unwrap_or_else(|_| "config.toml".to_string()))
+}
+
+pub fn models_refresh_interval() -> std::time::Duration {
+    dotenvy::dotenv().ok();
+
+    std::time::Duration::from_secs(env::var("MODELS_REFRESH_SECS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(600))
+}
+
+pub fn experiments_log_path() -> Option<PathBuf> {
+    dotenvy::dotenv().ok();
+
+    env::var("EXPERIMENTS_LOG_PATH").ok().map(PathBuf::from)
+}
+
+// Без токена административный API не подключается
+pub fn admin_token() -> Option<Secret<String>> {
+    dotenvy::dotenv().ok();
+
+    env::var("ADMIN_TOKEN")
+        .ok()
+        .filter(|token| !token.is_empty())
+        .map(Secret::new)
+}
+
+// Файл `.env`, который нашёл dotenvy; если его нет, отслеживается `.env` в текущем каталоге
+pub fn env_path() -> PathBuf {
+    dotenvy::dotenv().unwrap_or_else(|_| PathBuf::from(".env"))
+}
+
+// Сколько ждать завершения запросов после сигнала остановки
+pub fn shutdown_timeout() -> std::time::Duration {
+    dotenvy::dotenv().ok();
+
+    std::time::Duration::from_secs(env::var("SHUTDOWN_TIMEOUT_SECS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(30))
+}
+
+pub fn config_watch_interval() -> std::time::Duration {
+    dotenvy::dotenv().ok();
+
+    std::time::Duration::from_secs(env::var("CONFIG_WATCH_SECS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(5))
+}
+
+// Адреса через запятую: `host:port`, `tls://host:port` или `unix:///path/to.sock`
+pub fn listen_addresses() -> Vec<String> {
+    dotenvy::dotenv().ok();
+
+    env::var("LISTEN")
+        .unwrap_or_else(|_| "0.0.0.0:3000".to_string())
+        .split(',')
+        .map(str::trim)
+        .filter(|address| !address.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+#[derive(Clone, Debug)]
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    // Если задан, клиенты обязаны предъявить сертификат, подписанный этим CA
+    pub client_ca: Option<PathBuf>,
+}
+
+pub fn load_tls() -> Option<TlsConfig> {
+    dotenvy::dotenv().ok();
+
+    Some(TlsConfig {
+        cert: PathBuf::from(env::var("TLS_CERT_PATH").ok()?),
+        key: PathBuf::from(env::var("TLS_KEY_PATH").ok()?),
+        client_ca: env::var("TLS_CLIENT_CA_PATH").ok().map(PathBuf::from),
+    })
+}
+
+// За сколько до истечения обновлять временный токен провайдера
+pub fn token_refresh_skew() -> std::time::Duration {
+    dotenvy::dotenv().ok();
+
+    std::time::Duration::from_secs(env::var("TOKEN_REFRESH_SKEW_SECS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(60))
+}
+
+// Повторы по умолчанию для ответов, не прошедших проверку по response_format
+pub fn structured_output_retries() -> usize {
+    dotenvy::dotenv().ok();
+
+    structured_output_retries_from(|name| env::var(name).ok())
+}
+
+pub fn structured_output_retries_from(var: impl Fn(&str) -> Option<String>) -> usize {
+    var("STRUCTURED_OUTPUT_RETRIES")
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(2)
+}
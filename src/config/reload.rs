@@ -0,0 +1,144 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+/// Содержимое файла конфигурации и `.env`, из которого собрана конфигурация.
+#[derive(Clone, Debug, Default)]
+pub struct ConfigSource {
+    pub text: String,
+    pub env: BTreeMap<String, String>,
+}
+
+impl ConfigSource {
+    pub fn read(config_path: &Path, env_path: &Path) -> anyhow::Result<Self> {
+        let text = if config_path.exists() {
+            fs::read_to_string(config_path)?
+        } else {
+            String::new()
+        };
+
+        let env = if env_path.exists() {
+            dotenvy::from_path_iter(env_path)?.collect::<Result<BTreeMap<_, _>, _>>()?
+        } else {
+            BTreeMap::new()
+        };
+
+        Ok(Self { text, env })
+    }
+
+    pub fn hash(&self) -> String {
+        let mut hasher = DefaultHasher::new();
+        self.text.hash(&mut hasher);
+        self.env.hash(&mut hasher);
+        format!("{:016x}", hasher.finish())
+    }
+
+    // Значение переменной для этой версии `.env` без изменения окружения процесса.
+    // Как и у dotenvy, заданное снаружи окружение важнее файла; значения, попавшие в окружение
+    // из `.env` при запуске, берутся из текущего файла, поэтому удалённая из него переменная удалена
+    pub fn var(&self, startup: &ConfigSource, name: &str) -> Option<String> {
+        match std::env::var(name) {
+            Ok(value) if startup.env.get(name) != Some(&value) => Some(value),
+            _ => self.env.get(name).cloned(),
+        }
+    }
+
+    // Имена переменных окружения, которые добавлены, удалены или изменены; значения не выводятся
+    pub fn changed_env(&self, previous: &ConfigSource) -> Vec<String> {
+        let mut names = self.env
+            .keys()
+            .chain(previous.env.keys())
+            .filter(|name| self.env.get(*name) != previous.env.get(*name))
+            .cloned()
+            .collect::<Vec<_>>();
+        names.sort();
+        names.dedup();
+        names
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct RejectedConfig {
+    pub hash: String,
+    pub error: String,
+    pub rejected_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ConfigStatus {
+    pub version: u64,
+    pub hash: String,
+    pub path: String,
+    pub loaded_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rejected: Option<RejectedConfig>,
+}
+
+// Построчная разница между старым и новым текстом конфигурации
+pub fn diff_lines(old: &str, new: &str) -> Vec<String> {
+    let old = old.lines().collect::<Vec<_>>();
+    let new = new.lines().collect::<Vec<_>>();
+
+    // Длины наибольших общих подпоследовательностей суффиксов
+    let mut common = vec![vec![0u32; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut diff = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            i += 1;
+            j += 1;
+        } else if j < new.len() && (i == old.len() || common[i][j + 1] >= common[i + 1][j]) {
+            diff.push(format!("+ {}", new[j]));
+            j += 1;
+        } else {
+            diff.push(format!("- {}", old[i]));
+            i += 1;
+        }
+    }
+
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identical_texts_have_no_diff() {
+        assert!(diff_lines("a\nb\n", "a\nb\n").is_empty());
+        assert!(diff_lines("", "").is_empty());
+    }
+
+    #[test]
+    fn reports_added_removed_and_changed_lines() {
+        let old = "port = 8080\nlog = info\ncache = on\n";
+        let new = "port = 8080\nlog = debug\ncache = on\nstore = disk\n";
+        assert_eq!(diff_lines(old, new), ["+ log = debug", "- log = info", "+ store = disk"]);
+    }
+
+    #[test]
+    fn handles_empty_sides() {
+        assert_eq!(diff_lines("", "a\nb"), ["+ a", "+ b"]);
+        assert_eq!(diff_lines("a\nb", ""), ["- a", "- b"]);
+    }
+
+    #[test]
+    fn keeps_common_lines_around_moves() {
+        assert_eq!(diff_lines("a\nb\nc", "b\nc\na"), ["- a", "+ a"]);
+    }
+}
@@ -0,0 +1,249 @@
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures::{stream::BoxStream, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::server::TlsStream;
+use tokio_util::sync::CancellationToken;
+use tonic::transport::server::{Connected, TcpConnectInfo};
+use tonic::{transport::Server, Request, Response, Status};
+
+use crate::config::{self, TlsConfig};
+use crate::listeners::tls::{TlsListener, TlsReloader};
+
+use crate::llm::content::MessageContent;
+use crate::llm::encoding::EmbeddingOptions;
+use crate::llm::provider::{EmbeddingInput, LlmProvider, ServiceChatRequest, ServiceEmbeddingRequest};
+use crate::llm::requests::{Cancelled, DuplicateRequest};
+use crate::llm::router::Unavailable;
+use crate::llm::schema::StructuredOutputError;
+use crate::llm::services::UpstreamError;
+use crate::llm::{ChatEvent, ChatMessage, SamplingParams, Stop};
+
+pub mod pb {
+    tonic::include_proto!("llm.gateway.v1");
+
+    pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("llm_gateway_descriptor");
+}
+
+use pb::llm_gateway_server::{LlmGateway, LlmGatewayServer};
+
+pub struct GatewayService {
+    provider: Arc<LlmProvider>,
+}
+
+impl From<pb::ChatRequest> for ServiceChatRequest {
+    fn from(request: pb::ChatRequest) -> Self {
+        ServiceChatRequest {
+            provider: request.provider,
+            model: request.model,
+            messages: request.messages
+                .into_iter()
+                .map(|message| ChatMessage {
+                    role: message.role,
+                    content: message.content.map(MessageContent::Text),
+                    tool_calls: None,
+                    tool_call_id: message.tool_call_id,
+                    name: message.name,
+                    attachments: None,
+                })
+                .collect(),
+            temperature: request.temperature,
+            sampling: SamplingParams {
+                top_p: request.top_p,
+                max_tokens: request.max_tokens,
+                stop: (!request.stop.is_empty()).then_some(Stop::Many(request.stop)),
+                presence_penalty: request.presence_penalty,
+                frequency_penalty: request.frequency_penalty,
+                n: request.n,
+                seed: request.seed,
+                repetition_penalty: request.repetition_penalty,
+                logprobs: request.logprobs,
+            },
+            ..Default::default()
+        }
+    }
+}
+
+impl From<ChatEvent> for pb::ChatEvent {
+    fn from(event: ChatEvent) -> Self {
+        use pb::chat_event::Event;
+
+        let event = match event {
+            ChatEvent::Delta { content } => Event::Delta(content),
+            ChatEvent::ToolStarted { id, name } => Event::ToolStarted(pb::ToolEvent { id, name, error: None }),
+            ChatEvent::ToolFinished { id, name, error } => Event::ToolFinished(pb::ToolEvent { id, name, error }),
+            ChatEvent::Done { finish_reason, .. } => Event::Done(pb::Done { finish_reason }),
+        };
+
+        pb::ChatEvent { event: Some(event) }
+    }
+}
+
+#[tonic::async_trait]
+impl LlmGateway for GatewayService {
+    type StreamChatStream = BoxStream<'static, Result<pb::ChatEvent, Status>>;
+
+    async fn chat(
+        &self,
+        request: Request<pb::ChatRequest>,
+    ) -> Result<Response<pb::ChatResponse>, Status> {
+        let response = self.provider
+            .chat(request.into_inner().into()).await
+            .map_err(|e| status(e.as_ref()))?;
+
+        Ok(Response::new(pb::ChatResponse {
+            content: response.content,
+            sources: response.metadata.map(|metadata| metadata.sources).unwrap_or_default(),
+        }))
+    }
+
+    async fn stream_chat(
+        &self,
+        request: Request<pb::ChatRequest>,
+    ) -> Result<Response<Self::StreamChatStream>, Status> {
+        let stream = self.provider
+            .chat_stream(request.into_inner().into()).await
+            .map_err(|e| status(e.as_ref()))?;
+
+        Ok(Response::new(stream.map(chat_event).boxed()))
+    }
+
+    async fn embed(
+        &self,
+        request: Request<pb::EmbedRequest>,
+    ) -> Result<Response<pb::EmbedResponse>, Status> {
+        let request = request.into_inner();
+        let response = self.provider
+            .embedding(ServiceEmbeddingRequest {
+                provider: request.provider,
+                model: request.model,
+                input: EmbeddingInput::Batch(request.input),
+                options: EmbeddingOptions {
+                    normalize: request.normalize,
+                    dimensions: request.dimensions.map(|dimensions| dimensions as usize),
+                    ..Default::default()
+                },
+            }).await
+            .map_err(|e| status(e.as_ref()))?;
+
+        Ok(Response::new(pb::EmbedResponse {
+            embeddings: response.embeddings
+                .into_iter()
+                .map(|values| pb::Embedding { values })
+                .collect(),
+            prompt_tokens: response.usage.prompt_tokens,
+        }))
+    }
+}
+
+// Тип элементов потока задаёт tonic, поэтому крупный `Status` в `Err` не упаковать
+#[allow(clippy::result_large_err)]
+fn chat_event(event: anyhow::Result<ChatEvent>) -> Result<pb::ChatEvent, Status> {
+    event
+        .map(pb::ChatEvent::from)
+        .map_err(|e| status(e.as_ref()))
+}
+
+// Отделяет ошибки запроса от сбоев, после которых клиенту имеет смысл повторить запрос
+fn status(e: &(dyn std::error::Error + 'static)) -> Status {
+    let message = e.to_string();
+    let mut current = Some(e);
+    while let Some(error) = current {
+        if error.is::<DuplicateRequest>() {
+            return Status::already_exists(message);
+        }
+        if error.is::<Cancelled>() {
+            return Status::cancelled(message);
+        }
+        if error.is::<StructuredOutputError>() {
+            return Status::failed_precondition(message);
+        }
+        if let Some(upstream) = error.downcast_ref::<UpstreamError>() {
+            return match upstream.retryable() {
+                true => Status::unavailable(message),
+                false => Status::internal(message),
+            };
+        }
+        if error.is::<Unavailable>() || error.is::<reqwest::Error>() || error.is::<reqwest_middleware::Error>() {
+            return Status::unavailable(message);
+        }
+        current = error.source();
+    }
+    Status::invalid_argument(message)
+}
+
+// TLS-соединение для tonic, который без своих TLS-функций не знает о tokio-rustls
+struct TlsConnection(TlsStream<TcpStream>);
+
+impl Connected for TlsConnection {
+    type ConnectInfo = TcpConnectInfo;
+
+    fn connect_info(&self) -> Self::ConnectInfo {
+        self.0.get_ref().0.connect_info()
+    }
+}
+
+impl AsyncRead for TlsConnection {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for TlsConnection {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
+// С настроенным TLS gRPC принимает только TLS-соединения с теми же сертификатами
+// и, если задан TLS_CLIENT_CA_PATH, только клиентов с сертификатом этого CA
+pub async fn serve(
+    provider: Arc<LlmProvider>,
+    addr: SocketAddr,
+    tls: Option<TlsConfig>,
+    stop: CancellationToken,
+) -> anyhow::Result<()> {
+    let (health_reporter, health_service) = tonic_health::server::health_reporter();
+    health_reporter.set_serving::<LlmGatewayServer<GatewayService>>().await;
+
+    let reflection_service = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(pb::FILE_DESCRIPTOR_SET)
+        .build_v1()?;
+
+    let server = Server::builder()
+        .add_service(health_service)
+        .add_service(reflection_service)
+        .add_service(LlmGatewayServer::new(GatewayService { provider }));
+
+    match tls {
+        Some(tls) => {
+            let reloader = TlsReloader::new(tls, &[b"h2"])?;
+            reloader.start_watcher(config::config_watch_interval());
+            let incoming = TlsListener::new(TcpListener::bind(addr).await?, reloader)?
+                .into_stream()
+                .map(|(stream, _)| Ok::<_, io::Error>(TlsConnection(stream)));
+
+            println!("gRPC сервер запущен на {} с TLS", addr);
+            server.serve_with_incoming_shutdown(incoming, stop.cancelled_owned()).await?;
+        },
+        None => {
+            println!("gRPC сервер запущен на {}", addr);
+            server.serve_with_shutdown(addr, stop.cancelled_owned()).await?;
+        },
+    }
+
+    Ok(())
+}
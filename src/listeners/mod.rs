@@ -0,0 +1,156 @@
+pub mod tls;
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use axum::Router;
+use futures::future::try_join_all;
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+
+use crate::config::{self, TlsConfig};
+use tls::{TlsListener, TlsReloader};
+
+#[derive(Clone, Debug)]
+pub enum ListenAddr {
+    Tcp(SocketAddr),
+    Tls(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl ListenAddr {
+    pub fn parse(address: &str) -> anyhow::Result<Self> {
+        let parsed = if let Some(path) = address.strip_prefix("unix://") {
+            Self::Unix(PathBuf::from(path))
+        } else if let Some(address) = address.strip_prefix("tls://") {
+            Self::Tls(address.parse()?)
+        } else {
+            Self::Tcp(address.strip_prefix("tcp://").unwrap_or(address).parse()?)
+        };
+        Ok(parsed)
+    }
+}
+
+impl std::fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "http://{}", addr),
+            Self::Tls(addr) => write!(f, "https://{}", addr),
+            Self::Unix(path) => write!(f, "unix://{}", path.display()),
+        }
+    }
+}
+
+// Разбирает адреса из LISTEN и проверяет, что для TLS-адресов заданы сертификат и ключ
+pub fn load(tls: Option<&TlsConfig>) -> anyhow::Result<Vec<ListenAddr>> {
+    let addrs = config::listen_addresses()
+        .iter()
+        .map(|address| ListenAddr::parse(address)
+            .map_err(|e| anyhow::anyhow!("Некорректный адрес в LISTEN - {} - {}", address, e)))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    if addrs.is_empty() {
+        return Err(anyhow::anyhow!("LISTEN не содержит ни одного адреса"));
+    }
+    if tls.is_none() && addrs.iter().any(|addr| matches!(addr, ListenAddr::Tls(_))) {
+        return Err(anyhow::anyhow!("Для tls:// адресов нужны TLS_CERT_PATH и TLS_KEY_PATH"));
+    }
+
+    Ok(addrs)
+}
+
+// Права на Unix-сокет: подключаться могут владелец и его группа
+#[cfg(unix)]
+const UNIX_SOCKET_MODE: u32 = 0o660;
+
+// Запускает HTTP на всех адресах; завершается, когда все слушатели закрыли свои соединения
+pub async fn serve(
+    app: Router,
+    addrs: Vec<ListenAddr>,
+    tls: Option<TlsConfig>,
+    stop: CancellationToken,
+) -> anyhow::Result<()> {
+    let reloader = match tls {
+        Some(tls) => {
+            let reloader = TlsReloader::new(tls, &[b"http/1.1"])?;
+            reloader.start_watcher(config::config_watch_interval());
+            Some(reloader)
+        },
+        None => None,
+    };
+
+    let mut servers = Vec::new();
+    for addr in addrs {
+        let app = app.clone();
+        let stop = stop.clone().cancelled_owned();
+
+        let server: futures::future::BoxFuture<'static, anyhow::Result<()>> = match &addr {
+            ListenAddr::Tcp(address) => {
+                let listener = TcpListener::bind(address).await?;
+                Box::pin(async move {
+                    axum::serve(listener, app).with_graceful_shutdown(stop).await?;
+                    Ok(())
+                })
+            },
+            ListenAddr::Tls(address) => {
+                let reloader = reloader.clone().expect("TLS-адреса проверены при разборе LISTEN");
+                let listener = TlsListener::new(TcpListener::bind(address).await?, reloader)?;
+                Box::pin(async move {
+                    axum::serve(listener, app).with_graceful_shutdown(stop).await?;
+                    Ok(())
+                })
+            },
+            #[cfg(unix)]
+            ListenAddr::Unix(path) => {
+                let listener = bind_unix(path)?;
+                let path = path.clone();
+                Box::pin(async move {
+                    let result = axum::serve(listener, app).with_graceful_shutdown(stop).await;
+                    let _ = std::fs::remove_file(&path);
+                    result?;
+                    Ok(())
+                })
+            },
+            #[cfg(not(unix))]
+            ListenAddr::Unix(_) => {
+                return Err(anyhow::anyhow!("Unix-сокеты не поддерживаются на этой платформе"));
+            },
+        };
+
+        println!("HTTP-сервер слушает {}", addr);
+        servers.push(server);
+    }
+
+    try_join_all(servers).await?;
+    Ok(())
+}
+
+// Сокет создаётся в закрытой директории рядом с целевым путём, получает права и только потом
+// переносится на место, поэтому до смены прав к нему не подключиться
+#[cfg(unix)]
+fn bind_unix(path: &std::path::Path) -> anyhow::Result<tokio::net::UnixListener> {
+    use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+
+    // Сокет мог остаться от предыдущего запуска; другие файлы по этому пути не удаляются
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(anyhow::anyhow!("{} существует и не является сокетом", path.display()));
+        }
+    }
+
+    let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(std::path::Path::new("."));
+    let staging = parent.join(format!(".sock-{}", std::process::id()));
+    std::fs::DirBuilder::new().mode(0o700).create(&staging)?;
+
+    let staged = staging.join("s");
+    let result = tokio::net::UnixListener::bind(&staged)
+        .and_then(|listener| {
+            std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(UNIX_SOCKET_MODE))?;
+            std::fs::rename(&staged, path)?;
+            Ok(listener)
+        });
+    let _ = std::fs::remove_file(&staged);
+    let _ = std::fs::remove_dir(&staging);
+
+    Ok(result?)
+}
@@ -0,0 +1,171 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use axum::serve::Listener;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::rustls::pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{crypto::ring, RootCertStore, ServerConfig};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+
+use crate::config::TlsConfig;
+use crate::shutdown;
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const ACCEPT_QUEUE_SIZE: usize = 64;
+
+fn server_config(config: &TlsConfig, alpn: &[Vec<u8>]) -> anyhow::Result<ServerConfig> {
+    let certs = CertificateDer::pem_file_iter(&config.cert)?.collect::<Result<Vec<_>, _>>()?;
+    let key = PrivateKeyDer::from_pem_file(&config.key)?;
+
+    let provider = Arc::new(ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone()).with_safe_default_protocol_versions()?;
+    let builder = match &config.client_ca {
+        // mTLS: без сертификата, подписанного этим CA, соединение не устанавливается
+        Some(client_ca) => {
+            let mut roots = RootCertStore::empty();
+            for cert in CertificateDer::pem_file_iter(client_ca)? {
+                roots.add(cert?)?;
+            }
+            builder.with_client_cert_verifier(
+                WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider).build()?
+            )
+        },
+        None => builder.with_no_client_auth(),
+    };
+
+    let mut server_config = builder.with_single_cert(certs, key)?;
+    server_config.alpn_protocols = alpn.to_vec();
+    Ok(server_config)
+}
+
+// Отпечаток содержимого файлов сертификата, ключа и CA, по которому замечаются изменения
+fn fingerprint(config: &TlsConfig) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for path in [Some(&config.cert), Some(&config.key), config.client_ca.as_ref()].into_iter().flatten() {
+        fs::read(path).ok().hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+/// Настройки TLS, общие для всех TLS-слушателей. Новые соединения
+/// используют актуальный сертификат, установленные продолжают работать со старым.
+#[derive(Clone)]
+pub struct TlsReloader {
+    config: TlsConfig,
+    // Протоколы ALPN: http/1.1 для HTTP-слушателей, h2 для gRPC
+    alpn: Vec<Vec<u8>>,
+    acceptor: Arc<RwLock<TlsAcceptor>>,
+}
+
+impl TlsReloader {
+    pub fn new(config: TlsConfig, alpn: &[&[u8]]) -> anyhow::Result<Self> {
+        let alpn = alpn.iter().map(|protocol| protocol.to_vec()).collect::<Vec<_>>();
+        let acceptor = TlsAcceptor::from(Arc::new(server_config(&config, &alpn)?));
+        Ok(Self { config, alpn, acceptor: Arc::new(RwLock::new(acceptor)) })
+    }
+
+    fn acceptor(&self) -> TlsAcceptor {
+        self.acceptor.read().unwrap().clone()
+    }
+
+    // Перечитывает файлы при изменении; при ошибке остаётся прежний сертификат
+    pub fn start_watcher(&self, interval: Duration) {
+        let reloader = self.clone();
+
+        shutdown::spawn(async move {
+            let mut current = fingerprint(&reloader.config);
+            while !shutdown::sleep(interval).await {
+                let next = fingerprint(&reloader.config);
+                if next == current {
+                    continue;
+                }
+                current = next;
+
+                match server_config(&reloader.config, &reloader.alpn) {
+                    Ok(server_config) => {
+                        *reloader.acceptor.write().unwrap() = TlsAcceptor::from(Arc::new(server_config));
+                        println!("Сертификат TLS {} перезагружен", reloader.config.cert.display());
+                    },
+                    Err(e) => println!("Не удалось перезагрузить сертификат TLS, используется прежний: {}", e),
+                }
+            }
+        });
+    }
+}
+
+/// TCP-слушатель, отдающий axum соединения после TLS-рукопожатия.
+/// Рукопожатия идут параллельно, чтобы медленный клиент не задерживал остальных.
+pub struct TlsListener {
+    connections: mpsc::Receiver<(TlsStream<TcpStream>, SocketAddr)>,
+    local_addr: SocketAddr,
+}
+
+impl TlsListener {
+    pub fn new(listener: TcpListener, reloader: TlsReloader) -> std::io::Result<Self> {
+        let local_addr = listener.local_addr()?;
+        let (sender, connections) = mpsc::channel(ACCEPT_QUEUE_SIZE);
+
+        tokio::spawn(async move {
+            loop {
+                // Слушатель закрыт, когда axum перестал принимать соединения
+                let (stream, addr) = tokio::select! {
+                    _ = sender.closed() => break,
+                    accepted = listener.accept() => match accepted {
+                        Ok(accepted) => accepted,
+                        Err(e) => {
+                            println!("Ошибка при приёме соединения: {}", e);
+                            tokio::time::sleep(Duration::from_secs(1)).await;
+                            continue;
+                        },
+                    },
+                };
+
+                let acceptor = reloader.acceptor();
+                let sender = sender.clone();
+                tokio::spawn(async move {
+                    match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                        Ok(Ok(stream)) => {
+                            let _ = sender.send((stream, addr)).await;
+                        },
+                        Ok(Err(e)) => println!("Ошибка TLS-рукопожатия с {}: {}", addr, e),
+                        Err(_) => println!("Истекло время TLS-рукопожатия с {}", addr),
+                    }
+                });
+            }
+        });
+
+        Ok(Self { connections, local_addr })
+    }
+
+    // Соединения после рукопожатия для серверов, которые принимают поток соединений, как tonic
+    pub fn into_stream(self) -> impl futures::Stream<Item = (TlsStream<TcpStream>, SocketAddr)> {
+        futures::stream::unfold(self.connections, |mut connections| async move {
+            let connection = connections.recv().await?;
+            Some((connection, connections))
+        })
+    }
+}
+
+impl Listener for TlsListener {
+    type Io = TlsStream<TcpStream>;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.connections.recv().await {
+            Some(connection) => connection,
+            // Задача приёма завершается только вместе с получателем
+            None => std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> std::io::Result<Self::Addr> {
+        Ok(self.local_addr)
+    }
+}
@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+// Общий объём вложений в памяти; при превышении первыми удаляются те, что истекают раньше
+const MAX_STORE_SIZE: usize = 256 * 1024 * 1024;
+
+/// Файл, который модель вернула в ответе, например сгенерированное изображение.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Attachment {
+    pub file_id: String,
+    pub content_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(skip)]
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AttachmentFormat {
+    #[default]
+    Base64,
+    Url,
+}
+
+struct HostedFile {
+    content_type: String,
+    bytes: Vec<u8>,
+    expires_at: DateTime<Utc>,
+}
+
+/// Временное хранилище вложений, которые отдаются клиенту по ссылке.
+pub struct AttachmentStore {
+    base_url: String,
+    ttl: Duration,
+    files: RwLock<HashMap<String, HostedFile>>,
+}
+
+impl AttachmentStore {
+    pub fn new(base_url: String, ttl: Duration) -> Self {
+        Self { base_url, ttl, files: RwLock::new(HashMap::new()) }
+    }
+
+    // Заполняет data или url в зависимости от формата, исходные байты больше не нужны
+    pub fn publish(&self, attachment: &mut Attachment, format: AttachmentFormat) {
+        let bytes = std::mem::take(&mut attachment.bytes);
+
+        match format {
+            AttachmentFormat::Base64 => attachment.data = Some(STANDARD.encode(bytes)),
+            AttachmentFormat::Url => {
+                let token = Uuid::new_v4().to_string();
+                let expires_at = Utc::now() + self.ttl;
+
+                let mut files = self.files.write().unwrap();
+                files.retain(|_, file| file.expires_at > Utc::now());
+                let mut size = files.values().map(|file| file.bytes.len()).sum::<usize>() + bytes.len();
+                if size > MAX_STORE_SIZE {
+                    let mut oldest = files
+                        .iter()
+                        .map(|(token, file)| (file.expires_at, token.clone()))
+                        .collect::<Vec<_>>();
+                    oldest.sort();
+                    for (_, token) in oldest {
+                        if size <= MAX_STORE_SIZE {
+                            break;
+                        }
+                        if let Some(file) = files.remove(&token) {
+                            size -= file.bytes.len();
+                        }
+                    }
+                }
+                files.insert(token.clone(), HostedFile {
+                    content_type: attachment.content_type.clone(),
+                    bytes,
+                    expires_at,
+                });
+
+                attachment.url = Some(format!("{}/attachments/{}", self.base_url, token));
+                attachment.expires_at = Some(expires_at);
+            },
+        }
+    }
+
+    pub fn get(&self, token: &str) -> Option<(String, Vec<u8>)> {
+        self.files
+            .read()
+            .unwrap()
+            .get(token)
+            .filter(|file| file.expires_at > Utc::now())
+            .map(|file| (file.content_type.clone(), file.bytes.clone()))
+    }
+}
+
+// Идентификаторы файлов из тегов `<img src="...">` в ответе GigaChat
+pub fn file_references(content: &str) -> Vec<String> {
+    let mut references = Vec::new();
+    let mut rest = content;
+
+    while let Some(start) = rest.find("<img") {
+        rest = &rest[start + 4..];
+        let tag = &rest[..rest.find('>').unwrap_or(rest.len())];
+
+        let Some(src) = tag.find("src=") else {
+            continue;
+        };
+        let value = &tag[src + 4..];
+        let Some(quote) = value.chars().next().filter(|c| *c == '"' || *c == '\'') else {
+            continue;
+        };
+        if let Some(end) = value[1..].find(quote) {
+            references.push(value[1..1 + end].to_string());
+        }
+    }
+
+    references
+}
@@ -4,15 +4,22 @@ use std::time::Duration;
 use chrono::{DateTime, Utc};
 use chrono::serde::ts_milliseconds;
 
-use reqwest::Client;
-use reqwest_middleware::ClientBuilder;
+use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
 use reqwest_retry::policies::ExponentialBackoff;
 use reqwest_retry::RetryTransientMiddleware;
 use secrecy::{ExposeSecret, Secret};
+use serde::Serialize;
 use tonic::service::Interceptor;
 use uuid::Uuid;
 
-use crate::llm::{RETRIES, TIMEOUT};
+use crate::config::{token_refresh_skew, NetworkSettings};
+use crate::llm::{http, TIMEOUT};
+use crate::shutdown;
+
+const AUTH_RETRIES: u32 = 3;
+// Паузы между неудачными попытками обновления токена
+const BACKOFF_MIN: Duration = Duration::from_secs(5);
+const BACKOFF_MAX: Duration = Duration::from_secs(300);
 
 
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
@@ -22,20 +29,22 @@ struct LLMAuthResponse {
     expires_at: DateTime<Utc>
 }
 
-async fn auth(token: &str, scope: String, auth_url: String) -> anyhow::Result<LLMAuthResponse> {
+fn auth_client(network: &NetworkSettings) -> anyhow::Result<ClientWithMiddleware> {
+    // Немного повторов на транзиентные ошибки, дальше паузы между попытками задаёт TokenSource
     let retry_policy = ExponentialBackoff::builder()
-        .build_with_max_retries(RETRIES);
+        .build_with_max_retries(AUTH_RETRIES);
 
-    let client = Client::builder()
-        .use_native_tls()
+    let client = http::client_builder(network)?
         .connect_timeout(Duration::from_secs(TIMEOUT))
         .timeout(Duration::from_secs(TIMEOUT))
         .build()?;
 
-    let client = ClientBuilder::new(client)
+    Ok(ClientBuilder::new(client)
         .with(RetryTransientMiddleware::new_with_policy(retry_policy))
-        .build();
+        .build())
+}
 
+async fn auth(client: &ClientWithMiddleware, token: &str, scope: String, auth_url: String) -> anyhow::Result<LLMAuthResponse> {
     let mut params = HashMap::new();
     params.insert("scope", scope);
     let responce = client
@@ -61,40 +70,192 @@ async fn auth(token: &str, scope: String, auth_url: String) -> anyhow::Result<LL
     Ok(responce)
 }
 
+#[derive(Debug)]
+struct TokenState {
+    access_token: String,
+    expires_at: DateTime<Utc>,
+    refreshed_at: DateTime<Utc>,
+    // Неудачных попыток обновления подряд
+    failures: u32,
+    last_error: Option<String>,
+    last_attempt: DateTime<Utc>,
+}
+
+impl TokenState {
+    // За `skew` до истечения, но не раньше середины срока жизни токена
+    fn refresh_at(&self, skew: Duration) -> DateTime<Utc> {
+        let half_lifetime = ((self.expires_at - self.refreshed_at) / 2).max(chrono::Duration::zero());
+        self.expires_at - chrono::Duration::from_std(skew).unwrap_or(half_lifetime).min(half_lifetime)
+    }
+
+    fn retry_delay(&self) -> Duration {
+        BACKOFF_MIN
+            .saturating_mul(2u32.saturating_pow(self.failures.saturating_sub(1)))
+            .min(BACKOFF_MAX)
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TokenStatus {
+    pub valid: bool,
+    pub expires_at: DateTime<Utc>,
+    pub refreshed_at: DateTime<Utc>,
+    // Плановое обновление
+    pub refresh_at: DateTime<Utc>,
+    pub failures: u32,
+    // Ошибка последней неудачной попытки обновления, пока токен не обновлён
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug)]
+struct TokenSource {
+    client: ClientWithMiddleware,
+    auth_token: Secret<String>,
+    scope: String,
+    auth_url: String,
+    skew: Duration,
+    state: RwLock<TokenState>,
+    // Одновременно идёт не больше одного обновления
+    refreshing: tokio::sync::Mutex<()>,
+}
+
+impl TokenSource {
+    async fn fetch(&self) -> anyhow::Result<LLMAuthResponse> {
+        auth(&self.client, self.auth_token.expose_secret(), self.scope.clone(), self.auth_url.clone()).await
+    }
+
+    // `rejected` - токен, на который провайдер ответил 401; без него обновление плановое
+    async fn refresh(&self, rejected: Option<&str>) -> anyhow::Result<()> {
+        let _refreshing = self.refreshing.lock().await;
+
+        // Пока ждали, токен мог обновить другой запрос или плановое обновление
+        {
+            let state = self.state.read().unwrap();
+            let refreshed = match rejected {
+                Some(rejected) => state.access_token != rejected,
+                None => state.failures == 0 && Utc::now() < state.refresh_at(self.skew),
+            };
+            if refreshed {
+                return Ok(());
+            }
+            // После неудачи ответы 401 не обходят паузу: иначе с отозванным ключом
+            // каждый запрос сам обращался бы к серверу авторизации
+            let backoff = chrono::Duration::from_std(state.retry_delay()).unwrap_or_default();
+            if rejected.is_some() && state.failures > 0 && Utc::now() < state.last_attempt + backoff {
+                return Err(anyhow::anyhow!(
+                    "Токен {} не обновлён: {}", self.auth_url, state.last_error.as_deref().unwrap_or_default()
+                ));
+            }
+        }
+
+        match self.fetch().await {
+            Ok(LLMAuthResponse { access_token, expires_at }) => {
+                let mut state = self.state.write().unwrap();
+                if state.failures > 0 {
+                    println!("Токен {} обновлён после неудачных попыток: {}", self.auth_url, state.failures);
+                }
+                *state = TokenState {
+                    access_token,
+                    expires_at,
+                    refreshed_at: Utc::now(),
+                    failures: 0,
+                    last_error: None,
+                    last_attempt: Utc::now(),
+                };
+                Ok(())
+            },
+            Err(err) => {
+                let mut state = self.state.write().unwrap();
+                state.failures += 1;
+                state.last_error = Some(err.to_string());
+                state.last_attempt = Utc::now();
+                println!(
+                    "Не удалось обновить токен {} (попытка {}, следующая через {:?}): {}",
+                    self.auth_url, state.failures, state.retry_delay(), err
+                );
+                Err(err)
+            },
+        }
+    }
+
+    fn next_refresh(&self) -> Duration {
+        let state = self.state.read().unwrap();
+        if state.failures > 0 {
+            return state.retry_delay();
+        }
+        (state.refresh_at(self.skew) - Utc::now()).to_std().unwrap_or_default()
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct TokenInterceptor {
-    token: Arc<RwLock<String>>,
+    source: Arc<TokenSource>,
 }
 
 impl TokenInterceptor {
-    pub async fn new(auth_token: Secret<String>, scope: String, auth_url: String) -> anyhow::Result<Self> {
-        let LLMAuthResponse { access_token, expires_at } = auth(auth_token.expose_secret(), scope.clone(), auth_url.clone()).await?;
-        let token = Arc::new(RwLock::new(access_token));
-        let updatable = Arc::downgrade(&token);
-
-        tokio::spawn(async move {
-            tokio::time::sleep((expires_at - Utc::now()).to_std().unwrap_or(Duration::from_secs(60))).await;
-
-            while let Some(updatable) = updatable.upgrade() {
-                let LLMAuthResponse { access_token, expires_at } = match auth(auth_token.expose_secret(), scope.clone(), auth_url.clone()).await {
-                    Ok(t) => t,
-                    Err(_err) => {
-                        tokio::time::sleep(Duration::from_secs(5)).await;
-                        continue
-                    }
-                };
+    pub async fn new(auth_token: Secret<String>, scope: String, auth_url: String, network: &NetworkSettings) -> anyhow::Result<Self> {
+        let mut source = TokenSource {
+            client: auth_client(network)?,
+            auth_token,
+            scope,
+            auth_url,
+            skew: token_refresh_skew(),
+            state: RwLock::new(TokenState {
+                access_token: String::new(),
+                expires_at: Utc::now(),
+                refreshed_at: Utc::now(),
+                failures: 0,
+                last_error: None,
+                last_attempt: Utc::now(),
+            }),
+            refreshing: tokio::sync::Mutex::new(()),
+        };
+        let LLMAuthResponse { access_token, expires_at } = source.fetch().await?;
+        let state = source.state.get_mut().unwrap();
+        state.access_token = access_token;
+        state.expires_at = expires_at;
+        state.refreshed_at = Utc::now();
 
-                *updatable.write().unwrap() = access_token;
-                let sleep_duration = expires_at - Utc::now();
-                tokio::time::sleep(sleep_duration.to_std().unwrap_or(Duration::from_secs(5))).await;
+        let source = Arc::new(source);
+        let updatable = Arc::downgrade(&source);
+
+        // Между попытками задача не держит токен, и он освобождается вместе с сервисом
+        shutdown::spawn(async move {
+            while let Some(delay) = updatable.upgrade().map(|source| source.next_refresh()) {
+                if shutdown::sleep(delay).await {
+                    break;
+                }
+                let Some(source) = updatable.upgrade() else {
+                    break;
+                };
+                // Ошибка уже в журнале и в состоянии токена, следующая попытка после паузы
+                let _ = source.refresh(None).await;
             }
         });
 
-        Ok(Self { token })
+        Ok(Self { source })
     }
 
     pub fn get_token(&self) -> String {
-        self.token.read().unwrap().clone()
+        self.source.state.read().unwrap().access_token.clone()
+    }
+
+    // Обновляет токен после ответа 401; true, если запрос можно повторить с новым токеном
+    pub async fn reauthorize(&self, rejected: &str) -> bool {
+        self.source.refresh(Some(rejected)).await.is_ok()
+    }
+
+    pub fn status(&self) -> TokenStatus {
+        let state = self.source.state.read().unwrap();
+        TokenStatus {
+            valid: state.expires_at > Utc::now(),
+            expires_at: state.expires_at,
+            refreshed_at: state.refreshed_at,
+            refresh_at: state.refresh_at(self.source.skew),
+            failures: state.failures,
+            last_error: state.last_error.clone(),
+        }
     }
 }
 
@@ -102,7 +263,7 @@ impl Interceptor for TokenInterceptor {
     fn call(&mut self, mut req: tonic::Request<()>) -> Result<tonic::Request<()>, tonic::Status> {
         req.metadata_mut().append(
             "authorization",
-            format!("Bearer {}", self.token.read().unwrap()).parse().unwrap(),
+            format!("Bearer {}", self.get_token()).parse().unwrap(),
         );
 
         Ok(req)
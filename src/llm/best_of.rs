@@ -0,0 +1,76 @@
+use serde::{Deserialize, Serialize};
+
+use crate::llm::provider::ChatChoice;
+use crate::llm::ChatMessage;
+
+/// Способ выбора лучшего из нескольких сгенерированных вариантов.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Scorer {
+    /// Вариант с наибольшим средним logprob токенов
+    #[default]
+    Logprob,
+    /// Вариант, который выберет модель-судья
+    Judge(JudgeScorer),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct JudgeScorer {
+    pub provider: String,
+    pub model: String,
+    #[serde(default)]
+    pub criteria: Option<String>,
+}
+
+pub fn best_by_score(candidates: &[ChatChoice]) -> anyhow::Result<usize> {
+    candidates
+        .iter()
+        .enumerate()
+        .filter_map(|(index, candidate)| Some((index, candidate.score?)))
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(index, _)| index)
+        .ok_or_else(|| anyhow::anyhow!("Провайдер не вернул logprobs для оценки вариантов"))
+}
+
+pub fn judge_messages(
+    judge: &JudgeScorer,
+    messages: &[ChatMessage],
+    candidates: &[ChatChoice],
+) -> Vec<ChatMessage> {
+    let dialog = messages
+        .iter()
+        .filter(|message| message.role != "tool")
+        .map(|message| format!("{}: {}", message.role, message.text_content().unwrap_or_default()))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let answers = candidates
+        .iter()
+        .enumerate()
+        .map(|(index, candidate)| format!(
+            "Ответ {}:\n{}", index + 1, candidate.content.as_deref().unwrap_or_default()
+        ))
+        .collect::<Vec<_>>()
+        .join("\n\n");
+
+    let criteria = judge.criteria
+        .as_deref()
+        .unwrap_or("точность, полнота и следование инструкциям");
+
+    vec![
+        ChatMessage::text("system", format!(
+            "Ты оцениваешь ответы ассистента. Критерии: {criteria}. \
+            Выбери лучший ответ и верни только его номер."
+        )),
+        ChatMessage::text("user", format!("Диалог:\n{dialog}\n\n{answers}")),
+    ]
+}
+
+// Номер ответа из вердикта судьи, начиная с единицы
+pub fn parse_verdict(verdict: &str, candidates: usize) -> Option<usize> {
+    verdict
+        .split(|c: char| !c.is_ascii_digit())
+        .filter_map(|number| number.parse::<usize>().ok())
+        .find(|number| (1..=candidates).contains(number))
+        .map(|number| number - 1)
+}
@@ -0,0 +1,102 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::RwLock;
+
+use ringbuffer::{AllocRingBuffer, RingBuffer};
+
+use crate::config::SemanticCacheConfig;
+use crate::llm::{ChatMessage, ToolsStatus};
+use crate::llm::provider::ServiceChatRequest;
+
+struct CacheEntry {
+    scope: u64,
+    embedding: Vec<f32>,
+    content: Option<String>,
+}
+
+/// Кэш ответов по семантической близости последнего сообщения пользователя.
+///
+/// Записи разделены по провайдеру, модели, всей переписке до последнего вопроса
+/// и параметрам, влияющим на ответ, поэтому похожий вопрос к другой модели,
+/// в другом контексте или с другими настройками не совпадёт.
+pub struct SemanticCache {
+    pub provider: String,
+    pub model: String,
+    threshold: f32,
+    entries: RwLock<AllocRingBuffer<CacheEntry>>,
+}
+
+impl SemanticCache {
+    pub fn new(config: SemanticCacheConfig) -> Self {
+        Self {
+            provider: config.provider,
+            model: config.model,
+            threshold: config.threshold,
+            entries: RwLock::new(AllocRingBuffer::new(config.capacity.max(1))),
+        }
+    }
+
+    // Последнее сообщение пользователя сравнивается по близости, всё остальное должно совпасть точно
+    pub fn scope(request: &ServiceChatRequest, tools: &ToolsStatus) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        request.provider.to_uppercase().hash(&mut hasher);
+        request.model.hash(&mut hasher);
+        serde_json::to_string(&request.response_format).unwrap_or_default().hash(&mut hasher);
+        serde_json::to_string(&request.sampling).unwrap_or_default().hash(&mut hasher);
+        request.temperature.map(f32::to_bits).hash(&mut hasher);
+        (!request.skip_tools).then_some(tools.fingerprint).hash(&mut hasher);
+
+        let last_user = request.messages.iter().rposition(|message| message.role == "user");
+        for (index, message) in request.messages.iter().enumerate() {
+            if Some(index) != last_user {
+                serde_json::to_string(message).unwrap_or_default().hash(&mut hasher);
+            }
+        }
+
+        hasher.finish()
+    }
+
+    // Сообщения с вложениями не кэшируются: совпадение текста не означает совпадения запроса
+    pub fn last_user_message(messages: &[ChatMessage]) -> Option<String> {
+        messages
+            .iter()
+            .rev()
+            .find(|message| message.role == "user")
+            .and_then(|message| message.content.as_ref()?.plain_text())
+    }
+
+    pub fn lookup(&self, scope: u64, embedding: &[f32]) -> Option<Option<String>> {
+        let entries = self.entries.read().unwrap();
+
+        entries
+            .iter()
+            .filter(|entry| entry.scope == scope)
+            .map(|entry| (cosine_similarity(&entry.embedding, embedding), entry))
+            .filter(|(similarity, _)| *similarity >= self.threshold)
+            .max_by(|(a, _), (b, _)| a.total_cmp(b))
+            .map(|(_, entry)| entry.content.clone())
+    }
+
+    pub fn insert(&self, scope: u64, embedding: Vec<f32>, content: Option<String>) {
+        self.entries.write().unwrap().enqueue(CacheEntry { scope, embedding, content });
+    }
+}
+
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+
+    let (mut dot, mut norm_a, mut norm_b) = (0.0, 0.0, 0.0);
+    for (x, y) in a.iter().zip(b) {
+        dot += x * y;
+        norm_a += x * x;
+        norm_b += y * y;
+    }
+
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+
+    dot / (norm_a.sqrt() * norm_b.sqrt())
+}
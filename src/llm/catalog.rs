@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::config::file::ModelOverride;
+
+// Пока каталог пуст, запрос списка моделей обращается к провайдерам не чаще этого интервала
+const EMPTY_RETRY_INTERVAL: chrono::Duration = chrono::Duration::seconds(30);
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ModelCapabilities {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_length: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tools: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub streaming: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub embeddings_dimension: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vision: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pricing: Option<Pricing>,
+}
+
+impl ModelCapabilities {
+    // Заданные в `other` значения заменяют текущие
+    pub fn merge(&mut self, other: &ModelCapabilities) {
+        fn set<T: Clone>(field: &mut Option<T>, value: &Option<T>) {
+            if value.is_some() {
+                field.clone_from(value);
+            }
+        }
+
+        set(&mut self.context_length, &other.context_length);
+        set(&mut self.tools, &other.tools);
+        set(&mut self.streaming, &other.streaming);
+        set(&mut self.embeddings_dimension, &other.embeddings_dimension);
+        set(&mut self.vision, &other.vision);
+        set(&mut self.pricing, &other.pricing);
+    }
+}
+
+/// Цена за 1000 токенов.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Pricing {
+    pub input: f64,
+    pub output: f64,
+    pub currency: String,
+}
+
+/// Модель из ответа `/models` провайдера.
+#[derive(Clone, Debug, Deserialize)]
+pub struct UpstreamModel {
+    pub id: String,
+    #[serde(default)]
+    pub owned_by: Option<String>,
+    // GigaChat отличает модели чата от моделей эмбеддингов
+    #[serde(default, rename = "type")]
+    pub type_: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ModelInfo {
+    pub provider: String,
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owned_by: Option<String>,
+    #[serde(flatten)]
+    pub capabilities: ModelCapabilities,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ModelCatalogSnapshot {
+    pub models: Vec<ModelInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refreshed_at: Option<DateTime<Utc>>,
+}
+
+/// Кэш списка моделей по провайдерам с уточнениями из конфигурации.
+pub struct ModelCatalog {
+    overrides: RwLock<Vec<ModelOverride>>,
+    upstream: RwLock<HashMap<String, Vec<ModelInfo>>>,
+    refreshed_at: RwLock<Option<DateTime<Utc>>>,
+    // Последняя попытка обновления, в том числе неудачная
+    attempted_at: RwLock<Option<DateTime<Utc>>>,
+}
+
+impl ModelCatalog {
+    pub fn new(overrides: Vec<ModelOverride>) -> Self {
+        Self {
+            overrides: RwLock::new(overrides),
+            upstream: RwLock::new(HashMap::new()),
+            refreshed_at: RwLock::new(None),
+            attempted_at: RwLock::new(None),
+        }
+    }
+
+    pub fn set_overrides(&self, overrides: Vec<ModelOverride>) {
+        *self.overrides.write().unwrap() = overrides;
+    }
+
+    // Каталог ещё ни разу не обновился и с последней попытки прошло достаточно времени;
+    // попытка сразу записывается, чтобы одновременные запросы не обновляли его все
+    pub fn needs_refresh(&self) -> bool {
+        if self.refreshed_at.read().unwrap().is_some() {
+            return false;
+        }
+
+        let mut attempted_at = self.attempted_at.write().unwrap();
+        if attempted_at.is_some_and(|attempted_at| Utc::now() - attempted_at < EMPTY_RETRY_INTERVAL) {
+            return false;
+        }
+        *attempted_at = Some(Utc::now());
+        true
+    }
+
+    pub fn mark_attempted(&self) {
+        *self.attempted_at.write().unwrap() = Some(Utc::now());
+    }
+
+    // Заменяет список моделей провайдера; при ошибке запроса остаётся прежний
+    pub fn update(&self, provider: &str, models: Vec<ModelInfo>) {
+        self.upstream.write().unwrap().insert(provider.to_uppercase(), models);
+    }
+
+    pub fn mark_refreshed(&self) {
+        *self.refreshed_at.write().unwrap() = Some(Utc::now());
+    }
+
+    pub fn capabilities(&self, provider: &str, model: &str) -> Option<ModelCapabilities> {
+        self.snapshot(&[provider.to_uppercase()])
+            .models
+            .into_iter()
+            .find(|info| info.id == model)
+            .map(|info| info.capabilities)
+    }
+
+    pub fn snapshot(&self, providers: &[String]) -> ModelCatalogSnapshot {
+        let upstream = self.upstream.read().unwrap();
+
+        let mut models = upstream
+            .iter()
+            .filter(|(provider, _)| providers.contains(provider))
+            .flat_map(|(_, models)| models.iter().cloned())
+            .collect::<Vec<_>>();
+
+        for model_override in self.overrides.read().unwrap().iter() {
+            let provider = model_override.provider.to_uppercase();
+            if !providers.contains(&provider) {
+                continue;
+            }
+
+            let existing = models
+                .iter()
+                .position(|model| model.provider == provider && model.id == model_override.id);
+            match existing {
+                Some(index) if model_override.hidden => {
+                    models.remove(index);
+                },
+                Some(index) => models[index].capabilities.merge(&model_override.capabilities),
+                None if model_override.hidden => {},
+                // Модели, которых нет в ответе провайдера, добавляются из конфигурации
+                None => models.push(ModelInfo {
+                    provider,
+                    id: model_override.id.clone(),
+                    owned_by: None,
+                    capabilities: model_override.capabilities.clone(),
+                }),
+            }
+        }
+
+        models.sort_by(|a, b| (&a.provider, &a.id).cmp(&(&b.provider, &b.id)));
+
+        ModelCatalogSnapshot { models, refreshed_at: *self.refreshed_at.read().unwrap() }
+    }
+}
@@ -0,0 +1,87 @@
+use serde::{Deserialize, Serialize};
+
+/// Содержимое сообщения: строка или список типизированных частей.
+#[derive(Clone, Debug, Hash, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+#[derive(Clone, Debug, Hash, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrl },
+    ImageBase64 { media_type: String, data: String },
+    File { file_id: String },
+}
+
+#[derive(Clone, Debug, Hash, Serialize, Deserialize)]
+pub struct ImageUrl {
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl MessageContent {
+    // Текст всех текстовых частей, вложения пропускаются
+    pub fn to_text(&self) -> String {
+        match self {
+            MessageContent::Text(text) => text.clone(),
+            MessageContent::Parts(parts) => parts
+                .iter()
+                .filter_map(|part| match part {
+                    ContentPart::Text { text } => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+
+    // Текст сообщения, если в нём нет вложений
+    pub fn plain_text(&self) -> Option<String> {
+        match self {
+            MessageContent::Parts(parts) if parts.iter().any(|part| !matches!(part, ContentPart::Text { .. })) => None,
+            content => Some(content.to_text()),
+        }
+    }
+
+    // Заменяет текст сообщения, сохраняя вложения
+    pub fn with_text(self, text: String) -> MessageContent {
+        match self {
+            MessageContent::Text(_) => MessageContent::Text(text),
+            MessageContent::Parts(parts) => MessageContent::Parts(
+                std::iter::once(ContentPart::Text { text })
+                    .chain(parts.into_iter().filter(|part| !matches!(part, ContentPart::Text { .. })))
+                    .collect()
+            ),
+        }
+    }
+}
+
+impl From<String> for MessageContent {
+    fn from(text: String) -> Self {
+        MessageContent::Text(text)
+    }
+}
+
+/// Файл для загрузки в files API провайдера.
+#[derive(Clone, Debug)]
+pub struct FileUpload {
+    pub filename: String,
+    pub content_type: String,
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UploadedFile {
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub purpose: Option<String>,
+}
@@ -0,0 +1,131 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use half::f16;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EncodingFormat {
+    #[default]
+    Float,
+    Base64,
+    Float16,
+    Int8,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct EmbeddingOptions {
+    #[serde(default)]
+    pub normalize: bool,
+    #[serde(default)]
+    pub dimensions: Option<usize>,
+    #[serde(default)]
+    pub encoding_format: EncodingFormat,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum EncodedEmbedding {
+    Float(Vec<f32>),
+    Packed(String),
+}
+
+impl EmbeddingOptions {
+    pub fn apply(&self, embedding: &mut Vec<f32>) {
+        if let Some(dimensions) = self.dimensions {
+            embedding.truncate(dimensions);
+        }
+        if self.normalize {
+            l2_normalize(embedding);
+        }
+    }
+}
+
+impl EncodingFormat {
+    // Все упакованные форматы little-endian; int8 рассчитан на компоненты в [-1, 1],
+    // то есть на нормализованные векторы
+    pub fn encode(&self, embedding: Vec<f32>) -> EncodedEmbedding {
+        let bytes = match self {
+            EncodingFormat::Float => return EncodedEmbedding::Float(embedding),
+            EncodingFormat::Base64 => embedding
+                .iter()
+                .flat_map(|value| value.to_le_bytes())
+                .collect::<Vec<_>>(),
+            EncodingFormat::Float16 => embedding
+                .iter()
+                .flat_map(|value| f16::from_f32(*value).to_le_bytes())
+                .collect(),
+            EncodingFormat::Int8 => embedding
+                .iter()
+                .map(|value| (value.clamp(-1.0, 1.0) * 127.0).round() as i8 as u8)
+                .collect(),
+        };
+
+        EncodedEmbedding::Packed(STANDARD.encode(bytes))
+    }
+}
+
+pub fn l2_normalize(embedding: &mut [f32]) {
+    let norm = embedding.iter().map(|value| value * value).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        embedding.iter_mut().for_each(|value| *value /= norm);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packed(encoded: EncodedEmbedding) -> Vec<u8> {
+        match encoded {
+            EncodedEmbedding::Packed(data) => STANDARD.decode(data).unwrap(),
+            EncodedEmbedding::Float(_) => panic!("ожидался упакованный вектор"),
+        }
+    }
+
+    #[test]
+    fn truncates_before_normalizing() {
+        let options = EmbeddingOptions { normalize: true, dimensions: Some(2), ..Default::default() };
+        let mut embedding = vec![3.0, 4.0, 12.0];
+        options.apply(&mut embedding);
+        assert_eq!(embedding, [0.6, 0.8]);
+    }
+
+    #[test]
+    fn leaves_zero_vector_alone() {
+        let mut embedding = vec![0.0, 0.0];
+        l2_normalize(&mut embedding);
+        assert_eq!(embedding, [0.0, 0.0]);
+    }
+
+    #[test]
+    fn float_is_not_packed() {
+        match EncodingFormat::Float.encode(vec![0.5, -1.0]) {
+            EncodedEmbedding::Float(values) => assert_eq!(values, [0.5, -1.0]),
+            EncodedEmbedding::Packed(_) => panic!("float не должен упаковываться"),
+        }
+    }
+
+    #[test]
+    fn packs_little_endian() {
+        let bytes = packed(EncodingFormat::Base64.encode(vec![1.0, -2.5]));
+        let values = bytes
+            .chunks_exact(4)
+            .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(values, [1.0, -2.5]);
+
+        let bytes = packed(EncodingFormat::Float16.encode(vec![0.5, -2.0]));
+        let values = bytes
+            .chunks_exact(2)
+            .map(|chunk| f16::from_le_bytes(chunk.try_into().unwrap()).to_f32())
+            .collect::<Vec<_>>();
+        assert_eq!(values, [0.5, -2.0]);
+    }
+
+    #[test]
+    fn int8_clamps_and_rounds() {
+        let bytes = packed(EncodingFormat::Int8.encode(vec![1.0, -1.0, 0.5, 3.0, -7.0, 0.0]));
+        let values = bytes.into_iter().map(|byte| byte as i8).collect::<Vec<_>>();
+        assert_eq!(values, [127, -127, 64, 127, -127, 0]);
+    }
+}
@@ -0,0 +1,212 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::{Mutex, RwLock};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use ringbuffer::{AllocRingBuffer, RingBuffer};
+use serde::{Deserialize, Serialize};
+
+use crate::config::file::AliasTarget;
+use crate::llm::provider::{ServiceChatRequest, ServiceChatResponse};
+use crate::llm::{ChatMessage, Usage};
+
+// Записей на эксперимент в памяти, когда журнал в файл не ведётся
+const EXPERIMENT_HISTORY_SIZE: usize = 500;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExperimentMode {
+    // Копия запроса уходит кандидату в фоне, клиент получает ответ основной модели
+    #[default]
+    Shadow,
+    // Часть клиентов получает ответ кандидата, распределение закреплено за ключом клиента
+    Split,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExperimentConfig {
+    // Модель, как её указал клиент: алиас, маршрут или provider/model
+    pub model: String,
+    pub candidate: AliasTarget,
+    #[serde(default)]
+    pub mode: ExperimentMode,
+    pub percent: f64,
+}
+
+impl ExperimentConfig {
+    pub fn matches(&self, provider: &str, model: &str) -> bool {
+        if provider.is_empty() {
+            return self.model == model;
+        }
+        self.model
+            .split_once('/')
+            .is_some_and(|(name, id)| name.eq_ignore_ascii_case(provider) && id == model)
+    }
+
+    // Стабильное для ключа клиента решение, попадает ли он в долю кандидата
+    pub fn assign(&self, name: &str, key: &str) -> bool {
+        let mut hasher = DefaultHasher::new();
+        name.hash(&mut hasher);
+        key.hash(&mut hasher);
+        (hasher.finish() % 10000) as f64 / 100.0 < self.percent
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ArmResult {
+    pub provider: String,
+    pub model: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub latency_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+}
+
+impl ArmResult {
+    pub fn new(
+        provider: &str,
+        model: &str,
+        latency: Duration,
+        result: &Result<ServiceChatResponse, Box<dyn std::error::Error>>,
+    ) -> Self {
+        let (content, error, usage) = match result {
+            Ok(response) => (response.content.clone(), None, response.usage.clone()),
+            Err(e) => (None, Some(e.to_string()), None),
+        };
+
+        Self {
+            provider: provider.to_uppercase(),
+            model: model.to_string(),
+            content,
+            error,
+            latency_ms: latency.as_millis() as u64,
+            usage,
+        }
+    }
+}
+
+/// Решение по эксперименту для конкретного запроса.
+#[derive(Clone, Debug)]
+pub struct Assignment {
+    pub experiment: String,
+    pub mode: ExperimentMode,
+    pub candidate: bool,
+    pub target: AliasTarget,
+    pub client_key: Option<String>,
+}
+
+/// Копия запроса для кандидата вместе с результатом основной модели.
+pub struct ShadowJob {
+    pub experiment: String,
+    pub client_key: Option<String>,
+    pub request_id: String,
+    pub request: ServiceChatRequest,
+    pub control: ArmResult,
+}
+
+/// Одна строка выгрузки. В режиме shadow заполнены обе стороны,
+/// в режиме split только та, что ответила клиенту.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ExperimentRecord {
+    pub experiment: String,
+    pub mode: ExperimentMode,
+    pub request_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_key: Option<String>,
+    pub messages: Vec<ChatMessage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub control: Option<ArmResult>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub candidate: Option<ArmResult>,
+    pub recorded_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ExperimentInfo {
+    pub name: String,
+    pub model: String,
+    pub candidate: String,
+    pub mode: ExperimentMode,
+    pub percent: f64,
+    pub records: usize,
+}
+
+/// Результаты экспериментов: построчный JSON в файле, если задан путь,
+/// иначе последние записи в памяти.
+pub struct ExperimentLog {
+    records: RwLock<HashMap<String, AllocRingBuffer<ExperimentRecord>>>,
+    counts: RwLock<HashMap<String, usize>>,
+    log: Option<(PathBuf, Mutex<File>)>,
+}
+
+impl ExperimentLog {
+    pub fn new(path: Option<PathBuf>) -> anyhow::Result<Self> {
+        let log = match path {
+            Some(path) => {
+                let file = OpenOptions::new().create(true).append(true).open(&path)?;
+                Some((path, Mutex::new(file)))
+            },
+            None => None,
+        };
+
+        Ok(Self { records: RwLock::new(HashMap::new()), counts: RwLock::new(HashMap::new()), log })
+    }
+
+    pub fn record(&self, record: ExperimentRecord) {
+        *self.counts.write().unwrap().entry(record.experiment.clone()).or_default() += 1;
+
+        let Some((_, log)) = &self.log else {
+            self.records
+                .write()
+                .unwrap()
+                .entry(record.experiment.clone())
+                .or_insert_with(|| AllocRingBuffer::new(EXPERIMENT_HISTORY_SIZE))
+                .enqueue(record);
+            return;
+        };
+
+        let written = serde_json::to_string(&record)
+            .map_err(anyhow::Error::from)
+            .and_then(|line| Ok(writeln!(log.lock().unwrap(), "{line}")?));
+        if let Err(e) = written {
+            eprintln!("Failed to write experiment record: {}", e);
+        }
+    }
+
+    pub fn flush(&self) -> std::io::Result<()> {
+        match &self.log {
+            Some((_, log)) => log.lock().unwrap().sync_all(),
+            None => Ok(()),
+        }
+    }
+
+    // Записи с момента запуска
+    pub fn count(&self, experiment: &str) -> usize {
+        self.counts.read().unwrap().get(experiment).copied().unwrap_or_default()
+    }
+
+    pub fn export(&self, experiment: &str) -> anyhow::Result<Vec<ExperimentRecord>> {
+        let Some((path, _)) = &self.log else {
+            return Ok(self.records.read().unwrap().get(experiment).map(|records| records.to_vec()).unwrap_or_default());
+        };
+
+        // Строки, которые не удалось разобрать, например недописанная последняя, пропускаются
+        let mut records = Vec::new();
+        for line in BufReader::new(File::open(path)?).lines() {
+            if let Ok(record) = serde_json::from_str::<ExperimentRecord>(&line?) {
+                if record.experiment == experiment {
+                    records.push(record);
+                }
+            }
+        }
+        Ok(records)
+    }
+}
@@ -0,0 +1,316 @@
+use std::fs;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::redirect::Policy;
+use reqwest::{Certificate, Client, ClientBuilder, Identity, Proxy, Url};
+use tokio_rustls::rustls::{
+    self,
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    client::WebPkiServerVerifier,
+    crypto::ring as provider,
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, ServerName, UnixTime},
+    DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+
+use crate::config::NetworkSettings;
+
+const IMAGE_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_IMAGE_SIZE: usize = 20 * 1024 * 1024;
+const MAX_REDIRECTS: usize = 3;
+
+// HTTP-клиент провайдера с его корневыми сертификатами, клиентским сертификатом и прокси.
+// Без закрепления сертификатов используется native-tls, как и раньше
+pub fn client_builder(settings: &NetworkSettings) -> anyhow::Result<ClientBuilder> {
+    let mut builder = reqwest::Client::builder();
+
+    if let Some(proxy) = &settings.proxy {
+        builder = builder.proxy(Proxy::all(proxy)
+            .map_err(|e| anyhow::anyhow!("Некорректный адрес прокси - {} - {}", proxy, e))?);
+    }
+
+    if !settings.pinned_certs.is_empty() {
+        return Ok(builder.use_preconfigured_tls(pinned_tls(settings)?));
+    }
+
+    builder = builder.use_native_tls();
+    for path in &settings.ca_certs {
+        let pem = read(path)?;
+        for cert in Certificate::from_pem_bundle(&pem)? {
+            builder = builder.add_root_certificate(cert);
+        }
+    }
+    if let Some((cert, key)) = client_identity(settings)? {
+        builder = builder.identity(Identity::from_pkcs8_pem(&cert, &key)?);
+    }
+
+    Ok(builder)
+}
+
+// Клиент для изображений по ссылкам из запросов: только https и только публичные адреса.
+// Перенаправления проходит `download`, проверяя каждый адрес так же, как первый
+pub fn image_client(settings: &NetworkSettings) -> anyhow::Result<Client> {
+    let mut builder = client_builder(settings)?
+        .timeout(IMAGE_TIMEOUT)
+        .redirect(Policy::none());
+    // Через прокси адрес получателя разрешает прокси, а резолвер понадобился бы для самого прокси,
+    // который обычно во внутренней сети; тогда адреса проверяются заранее в `download`
+    if settings.proxy.is_none() {
+        builder = builder.dns_resolver(Arc::new(PublicResolver));
+    }
+    Ok(builder.build()?)
+}
+
+pub async fn download(client: &Client, url: &str) -> anyhow::Result<(String, Vec<u8>)> {
+    let mut url = Url::parse(url)?;
+    let mut redirects = 0;
+    let mut response = loop {
+        check_target(&url).await?;
+        let response = client.get(url.clone()).send().await?;
+        if !response.status().is_redirection() {
+            break response.error_for_status()?;
+        }
+
+        if redirects == MAX_REDIRECTS {
+            return Err(anyhow::anyhow!("Слишком много перенаправлений"));
+        }
+        redirects += 1;
+        let location = response.headers()
+            .get(reqwest::header::LOCATION)
+            .and_then(|value| value.to_str().ok())
+            .ok_or_else(|| anyhow::anyhow!("Перенаправление без адреса: {}", url))?;
+        url = url.join(location)?;
+    };
+    if response.content_length().is_some_and(|length| length > MAX_IMAGE_SIZE as u64) {
+        return Err(anyhow::anyhow!("Изображение больше {} байт", MAX_IMAGE_SIZE));
+    }
+    let content_type = response.headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("image/jpeg")
+        .to_string();
+
+    let mut bytes = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if bytes.len() + chunk.len() > MAX_IMAGE_SIZE {
+            return Err(anyhow::anyhow!("Изображение больше {} байт", MAX_IMAGE_SIZE));
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+
+    Ok((content_type, bytes))
+}
+
+// Через прокси резолвер не участвует, поэтому имя хоста проверяется перед каждым запросом
+async fn check_target(url: &Url) -> anyhow::Result<()> {
+    check_url(url)?;
+    if let Some(host) = url.host_str().filter(|host| host.parse::<IpAddr>().is_err()) {
+        for addr in tokio::net::lookup_host((host, url.port_or_known_default().unwrap_or(443))).await? {
+            check_ip(addr.ip())?;
+        }
+    }
+    Ok(())
+}
+
+fn check_url(url: &Url) -> anyhow::Result<()> {
+    if url.scheme() != "https" {
+        return Err(anyhow::anyhow!("Изображения загружаются только по https: {}", url));
+    }
+    let host = url.host_str().ok_or_else(|| anyhow::anyhow!("В ссылке нет хоста: {}", url))?;
+    // Для IP в ссылке резолвер не вызывается
+    match host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+        Ok(ip) => check_ip(ip),
+        Err(_) => Ok(()),
+    }
+}
+
+fn check_ip(ip: IpAddr) -> anyhow::Result<()> {
+    if is_public(ip) {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!("Адрес {} недоступен для загрузки изображений", ip))
+    }
+}
+
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_private() || ip.is_loopback() || ip.is_link_local() || ip.is_unspecified()
+                || ip.is_broadcast() || ip.is_documentation() || ip.is_multicast()
+                || a == 0
+                // 100.64.0.0/10, адреса за NAT провайдера
+                || (a == 100 && (64..128).contains(&b)))
+        },
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => !(ip.is_loopback() || ip.is_unspecified() || ip.is_multicast()
+                || ip.is_unique_local() || ip.is_unicast_link_local()),
+        },
+    }
+}
+
+// Проверяет адреса при каждом соединении, поэтому повторное разрешение имени
+// не подменит адрес на внутренний после проверки
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let addrs = tokio::net::lookup_host((host.as_str(), 0)).await?.collect::<Vec<_>>();
+            for addr in &addrs {
+                check_ip(addr.ip())?;
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+fn read(path: &std::path::Path) -> anyhow::Result<Vec<u8>> {
+    fs::read(path).map_err(|e| anyhow::anyhow!("Не удалось прочитать {} - {}", path.display(), e))
+}
+
+fn client_identity(settings: &NetworkSettings) -> anyhow::Result<Option<(Vec<u8>, Vec<u8>)>> {
+    match (&settings.client_cert, &settings.client_key) {
+        (Some(cert), Some(key)) => Ok(Some((read(cert)?, read(key)?))),
+        (None, None) => Ok(None),
+        _ => Err(anyhow::anyhow!("Клиентский сертификат и ключ задаются вместе")),
+    }
+}
+
+// Отпечаток в виде "AB:CD:..." или "abcd...", как его выводит openssl
+fn parse_fingerprint(pin: &str) -> anyhow::Result<Vec<u8>> {
+    let hex = pin.replace(':', "");
+    if hex.len() != 64 || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return Err(anyhow::anyhow!("Отпечаток сертификата - {} - должен быть SHA-256 в hex", pin));
+    }
+    // После проверки выше каждая пара символов - корректный байт
+    Ok((0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+        .collect())
+}
+
+fn pinned_tls(settings: &NetworkSettings) -> anyhow::Result<rustls::ClientConfig> {
+    let pins = settings.pinned_certs
+        .iter()
+        .map(|pin| parse_fingerprint(pin))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let mut roots = RootCertStore::empty();
+    // Системные сертификаты, которые не удалось разобрать, пропускаются
+    let (system, _) = roots.add_parsable_certificates(rustls_native_certs::load_native_certs().certs);
+    if system == 0 && settings.ca_certs.is_empty() {
+        return Err(anyhow::anyhow!("Не найдено ни одного корневого сертификата"));
+    }
+    for path in &settings.ca_certs {
+        for cert in CertificateDer::pem_slice_iter(&read(path)?) {
+            roots.add(cert?)?;
+        }
+    }
+
+    let crypto = Arc::new(provider::default_provider());
+    let verifier = PinnedVerifier {
+        inner: WebPkiServerVerifier::builder_with_provider(Arc::new(roots), crypto.clone()).build()?,
+        pins,
+    };
+    let builder = rustls::ClientConfig::builder_with_provider(crypto)
+        .with_safe_default_protocol_versions()?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier));
+
+    let config = match client_identity(settings)? {
+        Some((cert, key)) => {
+            let certs = CertificateDer::pem_slice_iter(&cert).collect::<Result<Vec<_>, _>>()?;
+            builder.with_client_auth_cert(certs, PrivateKeyDer::from_pem_slice(&key)?)?
+        },
+        None => builder.with_no_client_auth(),
+    };
+    Ok(config)
+}
+
+// Обычная проверка цепочки и имени сервера, после которой сертификат сервера
+// сверяется с закреплёнными отпечатками
+#[derive(Debug)]
+struct PinnedVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    pins: Vec<Vec<u8>>,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)?;
+
+        let fingerprint = ring::digest::digest(&ring::digest::SHA256, end_entity);
+        if !self.pins.iter().any(|pin| pin.as_slice() == fingerprint.as_ref()) {
+            return Err(rustls::Error::General(format!(
+                "Сертификат сервера {:?} не совпадает с закреплёнными отпечатками", server_name
+            )));
+        }
+
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PIN: &str = "00112233445566778899aabbccddeeff00112233445566778899AABBCCDDEEFF";
+
+    #[test]
+    fn parses_plain_and_colon_separated_fingerprints() {
+        let expected = [0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF]
+            .repeat(2);
+        assert_eq!(parse_fingerprint(PIN).unwrap(), expected);
+
+        let separated = PIN.as_bytes()
+            .chunks(2)
+            .map(|pair| std::str::from_utf8(pair).unwrap())
+            .collect::<Vec<_>>()
+            .join(":");
+        assert_eq!(parse_fingerprint(&separated).unwrap(), expected);
+    }
+
+    #[test]
+    fn rejects_malformed_fingerprints() {
+        assert!(parse_fingerprint(&PIN[..62]).is_err());
+        assert!(parse_fingerprint(&format!("{PIN}00")).is_err());
+        assert!(parse_fingerprint(&PIN.replace("ff", "zz")).is_err());
+        assert!(parse_fingerprint(&PIN.replacen("00", "+0", 1)).is_err());
+        // Многобайтовые символы не должны приводить к панике на границе среза
+        assert!(parse_fingerprint(&format!("{}ё{}", &PIN[..31], &PIN[..31])).is_err());
+    }
+}
@@ -1,27 +1,79 @@
+pub mod attachments;
 pub mod auth;
+pub mod best_of;
+pub mod cache;
+pub mod catalog;
+pub mod content;
+pub mod encoding;
+pub mod experiments;
+pub mod http;
+pub mod openai;
+pub mod pool;
 pub mod provider;
+pub mod requests;
+pub mod router;
+pub mod schema;
 pub mod services;
+pub mod usage;
 
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use async_trait::async_trait;
+use futures::stream::BoxStream;
 use serde_json::Value;
 
+use crate::llm::attachments::Attachment;
+use crate::llm::auth::TokenStatus;
+use crate::llm::catalog::{ModelCapabilities, UpstreamModel};
+use crate::llm::content::{FileUpload, MessageContent, UploadedFile};
 use crate::llm::provider::{ServiceChatRequest, ServiceChatResponse, ServiceEmbeddingRequest, ServiceEmbeddingResponse};
 
 const RETRIES: u32 = 100;
 const HISTORY_SIZE: usize = 100;
 const TIMEOUT: u64 = 100;
+const EMBEDDING_BATCH_SIZE: usize = 64;
+// Предел числа строк в одном запросе эмбеддингов GigaChat
+const GIGACHAT_EMBEDDING_BATCH_SIZE: usize = 100;
+const EMBEDDING_CONCURRENCY: usize = 4;
+const MAX_STRUCTURED_OUTPUT_RETRIES: usize = 5;
+const BEST_OF_LIMIT: u32 = 8;
+const ROUTER_WINDOW: usize = 100;
+const CIRCUIT_FAILURE_THRESHOLD: usize = 5;
+const CIRCUIT_COOLDOWN: u64 = 30;
+const SHADOW_QUEUE_SIZE: usize = 100;
+const SHADOW_CONCURRENCY: usize = 4;
+const PING_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ChatMessage {
     pub role: String,
-    pub content: Option<String>,
+    pub content: Option<MessageContent>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tool_calls: Option<Vec<ToolCall>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tool_call_id: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub name: Option<String>,
+    // Идентификаторы загруженных файлов для GigaChat
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attachments: Option<Vec<String>>,
+}
+
+impl ChatMessage {
+    pub fn text(role: &str, content: impl Into<String>) -> Self {
+        Self {
+            role: role.into(),
+            content: Some(MessageContent::Text(content.into())),
+            tool_calls: None,
+            tool_call_id: None,
+            name: None,
+            attachments: None,
+        }
+    }
+
+    pub fn text_content(&self) -> Option<String> {
+        self.content.as_ref().map(MessageContent::to_text)
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -65,6 +117,111 @@ pub struct ChatRequest {
     pub tools: Option<Vec<Tool>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tool_choice: Option<ToolChoice>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ResponseFormat>,
+    #[serde(flatten)]
+    pub sampling: SamplingParams,
+}
+
+/// Параметры сэмплирования, которые передаются провайдеру как есть.
+///
+/// Провайдеры поддерживают разные наборы параметров, лишние убираются
+/// через `SamplingParams::retain` до отправки запроса.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SamplingParams {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Stop>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub n: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repetition_penalty: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<bool>,
+}
+
+impl SamplingParams {
+    // Убирает параметры, которые не прошли проверку, и возвращает их имена
+    pub fn retain(&mut self, supported: impl Fn(&str) -> bool) -> Vec<&'static str> {
+        fn check<T>(
+            field: &mut Option<T>,
+            name: &'static str,
+            supported: &impl Fn(&str) -> bool,
+            dropped: &mut Vec<&'static str>,
+        ) {
+            if field.is_some() && !supported(name) {
+                *field = None;
+                dropped.push(name);
+            }
+        }
+
+        let mut dropped = Vec::new();
+        check(&mut self.top_p, "top_p", &supported, &mut dropped);
+        check(&mut self.max_tokens, "max_tokens", &supported, &mut dropped);
+        check(&mut self.stop, "stop", &supported, &mut dropped);
+        check(&mut self.presence_penalty, "presence_penalty", &supported, &mut dropped);
+        check(&mut self.frequency_penalty, "frequency_penalty", &supported, &mut dropped);
+        check(&mut self.n, "n", &supported, &mut dropped);
+        check(&mut self.seed, "seed", &supported, &mut dropped);
+        check(&mut self.repetition_penalty, "repetition_penalty", &supported, &mut dropped);
+        check(&mut self.logprobs, "logprobs", &supported, &mut dropped);
+        dropped
+    }
+
+    // Заполняет не заданные в запросе параметры значениями по умолчанию
+    pub fn fill_from(&mut self, defaults: &SamplingParams) {
+        fn fill<T: Clone>(field: &mut Option<T>, value: &Option<T>) {
+            if field.is_none() {
+                field.clone_from(value);
+            }
+        }
+
+        fill(&mut self.top_p, &defaults.top_p);
+        fill(&mut self.max_tokens, &defaults.max_tokens);
+        fill(&mut self.stop, &defaults.stop);
+        fill(&mut self.presence_penalty, &defaults.presence_penalty);
+        fill(&mut self.frequency_penalty, &defaults.frequency_penalty);
+        fill(&mut self.n, &defaults.n);
+        fill(&mut self.seed, &defaults.seed);
+        fill(&mut self.repetition_penalty, &defaults.repetition_penalty);
+        fill(&mut self.logprobs, &defaults.logprobs);
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Stop {
+    Single(String),
+    Many(Vec<String>),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseFormat {
+    Text,
+    JsonObject,
+    JsonSchema { json_schema: JsonSchemaFormat },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct JsonSchemaFormat {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub schema: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -98,6 +255,31 @@ pub struct Alternative {
     pub finish_reason: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub delta: Option<DeltaMessage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<ChoiceLogprobs>,
+}
+
+impl Alternative {
+    // Средний logprob токенов ответа, если провайдер его вернул
+    pub fn mean_logprob(&self) -> Option<f32> {
+        let tokens = self.logprobs.as_ref()?.content.as_ref()?;
+        if tokens.is_empty() {
+            return None;
+        }
+        Some(tokens.iter().map(|token| token.logprob).sum::<f32>() / tokens.len() as f32)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChoiceLogprobs {
+    #[serde(default)]
+    pub content: Option<Vec<TokenLogprob>>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TokenLogprob {
+    pub token: String,
+    pub logprob: f32,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -105,21 +287,79 @@ pub struct DeltaMessage {
     pub role: Option<String>,
     pub content: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub tool_calls: Option<Vec<ToolCall>>,
+    pub tool_calls: Option<Vec<ToolCallDelta>>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ToolCallDelta {
+    #[serde(default)]
+    pub index: usize,
+    pub id: Option<String>,
+    #[serde(rename = "type")]
+    pub type_: Option<String>,
+    pub function: Option<FunctionCallDelta>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FunctionCallDelta {
+    pub name: Option<String>,
+    pub arguments: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChatChunk {
+    pub choices: Vec<ChunkChoice>,
+    #[serde(default)]
+    pub usage: Option<Usage>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChunkChoice {
+    #[serde(default)]
+    pub index: i32,
+    #[serde(default)]
+    pub delta: Option<DeltaMessage>,
+    #[serde(default)]
+    pub finish_reason: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChatEvent {
+    Delta { content: String },
+    ToolStarted { id: String, name: String },
+    ToolFinished { id: String, name: String, error: Option<String> },
+    Done {
+        finish_reason: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        usage: Option<Usage>,
+    },
+}
+
+pub type ChatStream = BoxStream<'static, anyhow::Result<ChatEvent>>;
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct Usage {
     pub prompt_tokens: i32,
     pub completion_tokens: i32,
     pub total_tokens: i32,
 }
 
+impl Usage {
+    pub fn add(&mut self, other: &Usage) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.total_tokens += other.total_tokens;
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct EmbeddedResponse {
     pub object: String,
     pub model: String,
     pub data: Vec<EmbeddedData>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<EmbeddedUsage>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -127,10 +367,11 @@ pub struct EmbeddedData {
     pub object: String,
     pub index: u32,
     pub embedding: Vec<f32>,
-    pub usage: EmbeddedUsage,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<EmbeddedUsage>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct EmbeddedUsage {
     pub prompt_tokens: u32,
 }
@@ -141,8 +382,39 @@ pub struct EmbeddedRequest {
     pub input: Vec<String>,
 }
 
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ToolsStatus {
+    // Инструменты хотя бы раз загрузились; после ошибки перезагрузки действуют прежние
+    pub loaded: bool,
+    pub count: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reloaded_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    // Хэш описаний инструментов, меняется только вместе с набором инструментов
+    #[serde(skip)]
+    pub fingerprint: u64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ServiceHealth {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<TokenStatus>,
+    pub tools: ToolsStatus,
+}
+
 #[async_trait]
 pub trait LLMService: Send + Sync {
     async fn chat(&self, request: ServiceChatRequest) -> Result<ServiceChatResponse, Box<dyn std::error::Error>>;
+    async fn chat_stream(&self, request: ServiceChatRequest) -> Result<ChatStream, Box<dyn std::error::Error>>;
+    fn supports_param(&self, name: &str) -> bool;
+    async fn upload(&self, file: FileUpload) -> Result<UploadedFile, Box<dyn std::error::Error>>;
+    async fn files(&self) -> Result<Vec<UploadedFile>, Box<dyn std::error::Error>>;
+    async fn file(&self, id: &str) -> Result<UploadedFile, Box<dyn std::error::Error>>;
+    async fn file_content(&self, id: &str) -> Result<Attachment, Box<dyn std::error::Error>>;
+    async fn delete_file(&self, id: &str) -> Result<bool, Box<dyn std::error::Error>>;
+    async fn models(&self) -> Result<Vec<UpstreamModel>, Box<dyn std::error::Error>>;
+    fn model_capabilities(&self, model: &UpstreamModel) -> ModelCapabilities;
+    fn health(&self) -> ServiceHealth;
     async fn embedded(&self, request: ServiceEmbeddingRequest) -> Result<ServiceEmbeddingResponse, Box<dyn std::error::Error>>;
 }
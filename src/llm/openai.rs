@@ -0,0 +1,82 @@
+use serde::{Deserialize, Serialize};
+
+use crate::llm::encoding::{EmbeddingOptions, EncodedEmbedding};
+use crate::llm::provider::{EmbeddingInput, ServiceEmbeddingRequest, ServiceEmbeddingResponse};
+
+// OpenAI-совместимые маршруты: модель задаётся как `provider/model`,
+// либо провайдер передаётся отдельным полем
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct OpenAiEmbeddingRequest {
+    pub model: String,
+    pub input: EmbeddingInput,
+    #[serde(default)]
+    pub provider: Option<String>,
+    #[serde(flatten)]
+    pub options: EmbeddingOptions,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct OpenAiEmbeddingResponse {
+    pub object: String,
+    pub data: Vec<OpenAiEmbedding>,
+    pub model: String,
+    pub usage: OpenAiEmbeddingUsage,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct OpenAiEmbedding {
+    pub object: String,
+    pub index: usize,
+    pub embedding: EncodedEmbedding,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct OpenAiEmbeddingUsage {
+    pub prompt_tokens: u32,
+    pub total_tokens: u32,
+}
+
+impl TryFrom<OpenAiEmbeddingRequest> for ServiceEmbeddingRequest {
+    type Error = anyhow::Error;
+
+    fn try_from(request: OpenAiEmbeddingRequest) -> anyhow::Result<Self> {
+        let (provider, model) = match request.provider {
+            Some(provider) => (provider, request.model),
+            // Модель без провайдера считается алиасом из конфигурации
+            None => request.model
+                .split_once('/')
+                .map(|(provider, model)| (provider.to_string(), model.to_string()))
+                .unwrap_or_else(|| (String::new(), request.model)),
+        };
+
+        Ok(ServiceEmbeddingRequest {
+            provider,
+            model,
+            input: request.input,
+            options: request.options,
+        })
+    }
+}
+
+impl OpenAiEmbeddingResponse {
+    pub fn new(model: String, response: ServiceEmbeddingResponse, options: &EmbeddingOptions) -> Self {
+        Self {
+            object: "list".into(),
+            data: response.embeddings
+                .into_iter()
+                .enumerate()
+                .map(|(index, embedding)| OpenAiEmbedding {
+                    object: "embedding".into(),
+                    index,
+                    embedding: options.encoding_format.encode(embedding),
+                })
+                .collect(),
+            model,
+            usage: OpenAiEmbeddingUsage {
+                prompt_tokens: response.usage.prompt_tokens,
+                total_tokens: response.usage.prompt_tokens,
+            },
+        }
+    }
+}
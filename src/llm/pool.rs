@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use secrecy::Secret;
+use serde::{Deserialize, Serialize};
+
+use chrono::{DateTime, Utc};
+
+use crate::config::{reload::ConfigStatus, NetworkSettings};
+use crate::llm::auth::TokenStatus;
+use crate::llm::router::{TargetStats, Unavailable};
+use crate::llm::{LLMService, ToolsStatus};
+
+#[derive(Debug, Deserialize)]
+pub struct ProviderUpdate {
+    // По умолчанию совпадает с именем провайдера
+    #[serde(default)]
+    pub kind: Option<String>,
+    pub token: Secret<String>,
+    #[serde(default)]
+    pub scope: Option<String>,
+    #[serde(default)]
+    pub network: NetworkSettings,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ProviderStatus {
+    pub name: String,
+    pub kind: String,
+    pub draining: bool,
+    pub inflight: usize,
+    // Токен действителен и ни у одной модели провайдера нет разомкнутой цепи
+    pub healthy: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<TokenStatus>,
+    pub tools: ToolsStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_success: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_failure: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ping: Option<PingResult>,
+    pub targets: Vec<TargetStats>,
+}
+
+/// Результат активной проверки провайдера запросом списка моделей.
+#[derive(Clone, Debug, Serialize)]
+pub struct PingResult {
+    pub ok: bool,
+    pub latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct GatewayStatus {
+    pub ready: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub problems: Vec<String>,
+    pub config: ConfigStatus,
+    pub providers: Vec<ProviderStatus>,
+}
+
+impl GatewayStatus {
+    // Тексты ошибок провайдеров могут содержать адреса и ответы внутренних сервисов
+    pub fn redacted(mut self) -> Self {
+        for provider in &mut self.providers {
+            if let Some(token) = &mut provider.token {
+                token.last_error = None;
+            }
+            provider.ping = None;
+        }
+        self
+    }
+}
+
+#[derive(Clone)]
+pub struct ProviderEntry {
+    pub kind: String,
+    pub service: Arc<dyn LLMService>,
+    pub draining: bool,
+    // Провайдер настроен в `.env`, и перезагрузка конфигурации может его заменить или удалить
+    pub from_env: bool,
+}
+
+/// Набор провайдеров, который можно менять без перезапуска.
+/// Запросы в работе держат свою ссылку на сервис, поэтому замена
+/// или удаление провайдера их не прерывает.
+pub struct ProviderPool {
+    providers: RwLock<HashMap<String, ProviderEntry>>,
+}
+
+impl ProviderPool {
+    pub fn new() -> Self {
+        Self { providers: RwLock::new(HashMap::new()) }
+    }
+
+    // Возвращает true, если провайдер с таким именем был заменён. Вывод из работы сохраняется
+    pub fn insert(&self, name: &str, kind: &str, service: Arc<dyn LLMService>) -> bool {
+        let mut providers = self.providers.write().unwrap();
+        let draining = providers.get(&name.to_uppercase()).is_some_and(|entry| entry.draining);
+        providers
+            .insert(name.to_uppercase(), ProviderEntry { kind: kind.to_lowercase(), service, draining, from_env: false })
+            .is_some()
+    }
+
+    // Провайдер из `.env`; добавленный через API с тем же именем не заменяется, тогда возвращается false
+    pub fn insert_from_env(&self, name: &str, kind: &str, service: Arc<dyn LLMService>) -> bool {
+        let mut providers = self.providers.write().unwrap();
+        match providers.get_mut(&name.to_uppercase()) {
+            Some(entry) if !entry.from_env => false,
+            Some(entry) => {
+                entry.service = service;
+                true
+            },
+            None => {
+                providers.insert(name.to_uppercase(), ProviderEntry {
+                    kind: kind.to_lowercase(),
+                    service,
+                    draining: false,
+                    from_env: true,
+                });
+                true
+            },
+        }
+    }
+
+    pub fn remove(&self, name: &str) -> bool {
+        self.providers.write().unwrap().remove(&name.to_uppercase()).is_some()
+    }
+
+    // Удаляет провайдера, только если он настроен в `.env`
+    pub fn remove_from_env(&self, name: &str) -> bool {
+        let mut providers = self.providers.write().unwrap();
+        match providers.get(&name.to_uppercase()) {
+            Some(entry) if entry.from_env => providers.remove(&name.to_uppercase()).is_some(),
+            _ => false,
+        }
+    }
+
+    // None, если провайдера нет
+    pub fn is_from_env(&self, name: &str) -> Option<bool> {
+        self.providers.read().unwrap().get(&name.to_uppercase()).map(|entry| entry.from_env)
+    }
+
+    // Выведенный из работы провайдер не принимает новые запросы
+    pub fn set_draining(&self, name: &str, draining: bool) -> bool {
+        match self.providers.write().unwrap().get_mut(&name.to_uppercase()) {
+            Some(entry) => {
+                entry.draining = draining;
+                true
+            },
+            None => false,
+        }
+    }
+
+    // Ошибка без обёртки anyhow, чтобы после `?` её можно было распознать среди `Box<dyn Error>`
+    pub fn get(&self, name: &str) -> Result<Arc<dyn LLMService>, Unavailable> {
+        let providers = self.providers.read().unwrap();
+        match providers.get(&name.to_uppercase()) {
+            Some(entry) if entry.draining => Err(Unavailable(format!(
+                "Провайдер - {} - выводится из работы", name.to_uppercase()
+            ))),
+            Some(entry) => Ok(entry.service.clone()),
+            None => Err(Unavailable(format!(
+                "Модель - {} - не поддерживается", name.to_uppercase()
+            ))),
+        }
+    }
+
+    // Имена провайдеров, принимающих запросы
+    pub fn active(&self) -> Vec<String> {
+        self.services().into_iter().map(|(name, _)| name).collect()
+    }
+
+    pub fn services(&self) -> Vec<(String, Arc<dyn LLMService>)> {
+        self.providers
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, entry)| !entry.draining)
+            .map(|(name, entry)| (name.clone(), entry.service.clone()))
+            .collect()
+    }
+
+    // Все провайдеры, включая выводимые из работы
+    pub fn list(&self) -> Vec<(String, ProviderEntry)> {
+        let mut providers = self.providers
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, entry)| (name.clone(), entry.clone()))
+            .collect::<Vec<_>>();
+        providers.sort_by(|a, b| a.0.cmp(&b.0));
+        providers
+    }
+}
@@ -1,83 +1,1525 @@
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
 
+use chrono::Utc;
+use futures::StreamExt;
 use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use tokio::sync::{mpsc, Semaphore};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
 
 use crate::{
-    config::load, llm::{ChatMessage, LLMService}
+    config::{
+        attachment_ttl, config_path, config_watch_interval, env_path, experiments_log_path,
+        file::{AliasTarget, GatewayConfig, ModelAlias}, load, load_semantic_cache,
+        reload::{diff_lines, ConfigSource, ConfigStatus, RejectedConfig},
+        Model, ModelData, PROVIDER_ENV, models_refresh_interval, params_strict, params_strict_from, prompts_path, public_url, rag_template, rag_template_from, store_path, structured_output_retries, structured_output_retries_from, usage_log_path
+    },
+    llm::{
+        attachments::{Attachment, AttachmentFormat, AttachmentStore},
+        best_of::{best_by_score, judge_messages, parse_verdict, Scorer},
+        cache::SemanticCache,
+        catalog::{ModelCatalog, ModelCatalogSnapshot, ModelInfo},
+        content::{FileUpload, UploadedFile},
+        encoding::{EmbeddingOptions, EncodedEmbedding, EncodingFormat},
+        experiments::{
+            ArmResult, Assignment, ExperimentInfo, ExperimentLog, ExperimentMode,
+            ExperimentRecord, ShadowJob
+        },
+        pool::{GatewayStatus, PingResult, ProviderPool, ProviderStatus, ProviderUpdate},
+        requests::{Cancelled, RequestRegistry},
+        router::{estimate_tokens, into_send, should_fall_back, Candidate, CircuitState, Router, TargetStats, Unavailable},
+        usage::{RequestStatus, UsageRecord, UsageTracker},
+        ChatEvent, ChatMessage, ChatStream, EmbeddedUsage, LLMService, ResponseFormat, SamplingParams, Usage, BEST_OF_LIMIT,
+        PING_TIMEOUT, SHADOW_CONCURRENCY, SHADOW_QUEUE_SIZE
+    },
+    prompts::{PromptRegistry, PromptTemplateInfo},
+    shutdown,
+    store::{CollectionInfo, CollectionSettings, Document, SearchHit, VectorStore}
 };
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Default, Deserialize)]
 pub struct ServiceChatRequest {
+    #[serde(default)]
     pub provider: String,
+    #[serde(default)]
     pub model: String,
+    #[serde(default)]
     pub messages: Vec<ChatMessage>,
-    #[serde(default = "default_temperature")]
-    pub temperature: f32
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    #[serde(default)]
+    pub template: Option<String>,
+    #[serde(default)]
+    pub variables: HashMap<String, String>,
+    #[serde(default)]
+    pub retrieval: Option<RetrievalOptions>,
+    #[serde(default)]
+    pub response_format: Option<ResponseFormat>,
+    // Сколько раз переспросить модель, если ответ не прошёл проверку по response_format
+    #[serde(default)]
+    pub response_retries: Option<usize>,
+    #[serde(flatten)]
+    pub sampling: SamplingParams,
+    #[serde(default)]
+    pub best_of: Option<u32>,
+    #[serde(default)]
+    pub scorer: Scorer,
+    #[serde(default)]
+    pub attachment_format: AttachmentFormat,
+    // Ключ клиента, за которым закрепляется вариант A/B-эксперимента
+    #[serde(default)]
+    pub client_key: Option<String>,
+    #[serde(skip)]
+    pub fallback: Vec<AliasTarget>,
+    #[serde(skip)]
+    pub route: Option<String>,
+    #[serde(skip)]
+    pub request_id: Option<String>,
+    // Без инструментов шлюза: их побочные эффекты не должны повторяться, например в теневых копиях
+    #[serde(skip)]
+    pub skip_tools: bool,
+    #[serde(skip)]
+    pub cancel: CancellationToken
 }
 fn default_temperature() -> f32 { 0.1 }
 
+#[derive(Clone, Debug, Deserialize)]
+pub struct RetrievalOptions {
+    pub collection: String,
+    #[serde(default = "default_top_k")]
+    pub top_k: usize,
+    #[serde(default)]
+    pub filter: HashMap<String, Value>,
+    #[serde(default)]
+    pub template: Option<String>
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct ServiceEmbeddingRequest {
+    #[serde(default)]
     pub provider: String,
     pub model: String,
-    pub input: String
+    pub input: EmbeddingInput,
+    #[serde(flatten)]
+    pub options: EmbeddingOptions
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum EmbeddingInput {
+    Single(String),
+    Batch(Vec<String>)
+}
+
+impl EmbeddingInput {
+    pub fn into_vec(self) -> Vec<String> {
+        match self {
+            EmbeddingInput::Single(input) => vec![input],
+            EmbeddingInput::Batch(inputs) => inputs,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct ServiceChatResponse {
-    pub content: Option<String>
+    pub content: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<ResponseMetadata>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<Attachment>,
+    #[serde(default, skip_serializing_if = "single_choice")]
+    pub choices: Vec<ChatChoice>,
+    #[serde(skip)]
+    pub semantic_cache_hit: bool,
+    #[serde(skip)]
+    pub dropped_params: Vec<String>,
+    #[serde(skip)]
+    pub provider: String,
+    #[serde(skip)]
+    pub model: String,
+    #[serde(skip)]
+    pub route: Option<String>
+}
+
+fn single_choice(choices: &[ChatChoice]) -> bool { choices.len() <= 1 }
+
+impl ServiceChatResponse {
+    pub fn from_choices(choices: Vec<ChatChoice>, usage: Usage) -> Self {
+        Self {
+            content: choices.first().and_then(|choice| choice.content.clone()),
+            attachments: choices.first().map(|choice| choice.attachments.clone()).unwrap_or_default(),
+            usage: Some(usage),
+            choices,
+            ..Default::default()
+        }
+    }
+
+    fn into_choices(self) -> Vec<ChatChoice> {
+        if !self.choices.is_empty() {
+            return self.choices;
+        }
+        vec![ChatChoice { content: self.content, attachments: self.attachments, ..Default::default() }]
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ChatChoice {
+    pub index: u32,
+    pub content: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finish_reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub score: Option<f32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<Attachment>
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ResponseMetadata {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sources: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub selected: Option<u32>
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ServiceEmbeddingResponse {
-    pub content: Vec<f32>
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub content: Vec<f32>,
+    pub embeddings: Vec<Vec<f32>>,
+    pub usage: EmbeddedUsage
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EncodedEmbeddingResponse {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<EncodedEmbedding>,
+    pub embeddings: Vec<EncodedEmbedding>,
+    pub usage: EmbeddedUsage
+}
+
+impl ServiceEmbeddingResponse {
+    pub fn encode(self, format: EncodingFormat) -> EncodedEmbeddingResponse {
+        EncodedEmbeddingResponse {
+            content: if self.content.is_empty() { None } else { Some(format.encode(self.content)) },
+            embeddings: self.embeddings.into_iter().map(|embedding| format.encode(embedding)).collect(),
+            usage: self.usage,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct UpsertDocument {
+    #[serde(flatten)]
+    pub document: Document,
+    #[serde(default)]
+    pub vector: Option<Vec<f32>>
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct CollectionUpsertRequest {
+    pub documents: Vec<UpsertDocument>
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct CollectionUpsertResponse {
+    pub upserted: usize
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct CollectionQueryRequest {
+    #[serde(default)]
+    pub text: Option<String>,
+    #[serde(default)]
+    pub vector: Option<Vec<f32>>,
+    #[serde(default = "default_top_k")]
+    pub top_k: usize,
+    #[serde(default)]
+    pub filter: HashMap<String, Value>
+}
+fn default_top_k() -> usize { 5 }
+
+#[derive(Clone, Debug, Serialize)]
+pub struct CollectionQueryResponse {
+    pub hits: Vec<SearchHit>
 }
 
 pub struct LlmProvider {
-    providers: HashMap<String, Box<dyn LLMService>>
+    providers: ProviderPool,
+    semantic_cache: Option<SemanticCache>,
+    store: VectorStore,
+    rag_template: RwLock<String>,
+    requests: Arc<RequestRegistry>,
+    prompts: Arc<PromptRegistry>,
+    attachments: AttachmentStore,
+    catalog: ModelCatalog,
+    // Алиасы, маршруты и эксперименты заменяются целиком при перезагрузке конфигурации
+    config: RwLock<Arc<GatewayConfig>>,
+    // Применённое содержимое файлов; блокировка не даёт двум перезагрузкам идти одновременно
+    config_source: tokio::sync::Mutex<ConfigSource>,
+    // `.env` при запуске, чтобы отличать его значения в окружении процесса от заданных снаружи
+    startup_source: ConfigSource,
+    config_status: RwLock<ConfigStatus>,
+    router: Router,
+    experiment_log: ExperimentLog,
+    shadow: mpsc::Sender<ShadowJob>,
+    shadow_queue: Mutex<Option<mpsc::Receiver<ShadowJob>>>,
+    params_strict: AtomicBool,
+    structured_output_retries: AtomicUsize,
 }
 
 impl LlmProvider {
     pub async fn new() -> anyhow::Result<Self> {
-        let mut providers = HashMap::<String, Box<dyn LLMService>>::new();
+        let providers = ProviderPool::new();
         
         let llms = load();
         for llm in llms {
             match llm.get_service().await {
                 Some(service) => {
-                    providers.insert(
-                        llm.to_string().to_uppercase(),
-                        service
+                    providers.insert_from_env(
+                        &llm.to_string(),
+                        &llm.to_string(),
+                        Arc::from(service)
                     ); 
                 },
                 None => println!("Невозможно получить сервис {:?}", llm.to_string()),
             } 
         }
         
-        Ok(Self { providers })
+        let semantic_cache = load_semantic_cache().map(SemanticCache::new);
+
+        let store = VectorStore::open(store_path())?;
+
+        let requests = Arc::new(RequestRegistry::new(UsageTracker::new(usage_log_path())?));
+
+        if !config_path().exists() {
+            println!("Файл конфигурации {} не найден. Используются значения по умолчанию.", config_path().display());
+        }
+        let config_source = ConfigSource::read(&config_path(), &env_path())?;
+        let config = GatewayConfig::parse(&config_source.text)?;
+        let config_status = ConfigStatus {
+            version: 1,
+            hash: config_source.hash(),
+            path: config_path().display().to_string(),
+            loaded_at: Utc::now(),
+            rejected: None,
+        };
+
+        let prompts = Arc::new(PromptRegistry::new(prompts_path()));
+        prompts.start_watcher();
+
+        let (shadow, shadow_queue) = mpsc::channel(SHADOW_QUEUE_SIZE);
+
+        Ok(Self {
+            providers,
+            semantic_cache,
+            store,
+            rag_template: RwLock::new(rag_template()),
+            requests,
+            prompts,
+            attachments: AttachmentStore::new(public_url(), attachment_ttl()),
+            catalog: ModelCatalog::new(config.models.clone()),
+            config: RwLock::new(Arc::new(config)),
+            startup_source: config_source.clone(),
+            config_source: tokio::sync::Mutex::new(config_source),
+            config_status: RwLock::new(config_status),
+            router: Router::new(),
+            experiment_log: ExperimentLog::new(experiments_log_path())?,
+            shadow,
+            shadow_queue: Mutex::new(Some(shadow_queue)),
+            params_strict: AtomicBool::new(params_strict()),
+            structured_output_retries: AtomicUsize::new(structured_output_retries()),
+        })
     }
     
     pub async fn chat(
+        &self,
+        mut request: ServiceChatRequest,
+    ) -> Result<ServiceChatResponse, Box<dyn std::error::Error>> {
+        let request_id = request.request_id
+            .get_or_insert_with(|| Uuid::new_v4().to_string())
+            .clone();
+        let assignment = self.assign_experiment(&mut request);
+
+        self.resolve(&mut request)?;
+        let mut fallback = std::mem::take(&mut request.fallback).into_iter();
+        let sampling = request.sampling.clone();
+
+        let started = Instant::now();
+        let mut guard = self.requests.begin(&request_id, request.client_key.as_deref(), &request.provider, &request.model)?;
+        request.cancel = guard.token();
+
+        // Цели алиаса пробуются по очереди, пока одна не ответит или запрос не отменят
+        let result = loop {
+            request.sampling = sampling.clone();
+            let available = fallback.len() == 0 || !self.router.is_open(&request.provider, &request.model);
+            let result = match self.negotiate(&mut request) {
+                Ok(_) if !available => Err(Unavailable(format!(
+                    "Цепь {}/{} разомкнута", request.provider.to_uppercase(), request.model
+                )).into()),
+                Ok(dropped) => {
+                    let started = Instant::now();
+                    let result = self.dispatch_chat(request.clone()).await;
+                    self.observe(&request, started.elapsed(), &result);
+
+                    result.map(|mut response| {
+                        response.dropped_params = dropped;
+                        response
+                    })
+                },
+                Err(e) => Err(e.into()),
+            };
+
+            match result {
+                Err(e) if should_fall_back(e.as_ref()) => match fallback.next() {
+                    Some(target) => {
+                        println!(
+                            "Ошибка {}/{}: {}. Переключение на {}/{}",
+                            request.provider, request.model, e, target.provider, target.model
+                        );
+                        request.provider = target.provider;
+                        request.model = target.model;
+                        guard.retarget(&request.provider, &request.model);
+                    },
+                    None => break Err(e),
+                },
+                result => break result,
+            }
+        };
+        let result = result.map(|mut response| {
+            self.publish_attachments(&mut response, request.attachment_format);
+            response.provider = request.provider.clone();
+            response.model = request.model.clone();
+            response.route = request.route.clone();
+            response
+        });
+        if let Some(assignment) = assignment {
+            request.sampling = sampling;
+            self.track_experiment(assignment, &request, started.elapsed(), &result);
+        }
+        match &result {
+            Ok(response) => guard.finish(RequestStatus::Completed, response.usage.as_ref()),
+            Err(e) if e.is::<Cancelled>() => guard.finish(RequestStatus::Cancelled, None),
+            Err(_) => guard.finish(RequestStatus::Failed, None),
+        }
+
+        result
+    }
+
+    async fn dispatch_chat(
+        &self,
+        mut request: ServiceChatRequest,
+    ) -> Result<ServiceChatResponse, Box<dyn std::error::Error>> {
+        request.response_retries.get_or_insert(self.structured_output_retries.load(Ordering::Relaxed));
+        let sources = match request.retrieval.take() {
+            Some(retrieval) => Some(self.retrieve(&mut request.messages, retrieval).await?),
+            None => None,
+        };
+
+        // Ответ зависит от найденного контекста или содержит несколько вариантов,
+        // поэтому семантический кэш не используется
+        let uncached = sources.is_some() || request.sampling.n.is_some_and(|n| n > 1);
+        let mut response = match request.best_of {
+            Some(count) => self.best_of(request, count).await?,
+            None if uncached => self.service(&request.provider)?.chat(request).await?,
+            None => self.cached_chat(request).await?,
+        };
+        if let Some(sources) = sources {
+            response.metadata.get_or_insert_with(ResponseMetadata::default).sources = sources;
+        }
+
+        Ok(response)
+    }
+
+    // Генерирует несколько вариантов и возвращает лучший по выбранной оценке
+    async fn best_of(
+        &self,
+        mut request: ServiceChatRequest,
+        count: u32,
+    ) -> Result<ServiceChatResponse, Box<dyn std::error::Error>> {
+        if count == 0 || count > BEST_OF_LIMIT {
+            return Err(anyhow::anyhow!("best_of должен быть от 1 до {}", BEST_OF_LIMIT).into());
+        }
+        if request.sampling.n.is_some_and(|n| n > 1) {
+            return Err(anyhow::anyhow!("n и best_of нельзя использовать вместе").into());
+        }
+
+        let service = self.service(&request.provider)?;
+        if matches!(request.scorer, Scorer::Logprob) {
+            if !service.supports_param("logprobs") {
+                return Err(anyhow::anyhow!(
+                    "Провайдер {} не возвращает logprobs, используйте scorer judge",
+                    request.provider.to_uppercase()
+                ).into());
+            }
+            request.sampling.logprobs = Some(true);
+        } else if service.supports_param("logprobs") {
+            // Запасная оценка на случай, если вердикт судьи не удастся разобрать
+            request.sampling.logprobs = Some(true);
+        }
+
+        // Без нативного n варианты запрашиваются параллельно
+        let mut usage = Usage::default();
+        let mut candidates = Vec::new();
+        if service.supports_param("n") {
+            request.sampling.n = Some(count);
+            let response = service.chat(request.clone()).await?;
+            usage.add(&response.usage.clone().unwrap_or_default());
+            candidates = response.into_choices();
+        } else {
+            request.sampling.n = None;
+            let responses = futures::future::try_join_all((0..count).map(|_| async {
+                service.chat(request.clone()).await.map_err(into_send)
+            })).await;
+            let responses = match responses {
+                Ok(responses) => responses,
+                Err(_) if request.cancel.is_cancelled() => return Err(Cancelled.into()),
+                Err(e) => return Err(e),
+            };
+            for response in responses {
+                usage.add(&response.usage.clone().unwrap_or_default());
+                candidates.extend(response.into_choices().into_iter().take(1));
+            }
+        }
+        for (index, candidate) in candidates.iter_mut().enumerate() {
+            candidate.index = index as u32;
+        }
+
+        let selected = match &request.scorer {
+            Scorer::Logprob => best_by_score(&candidates)?,
+            Scorer::Judge(judge) => {
+                let verdict = self.service(&judge.provider)?
+                    .chat(ServiceChatRequest {
+                        provider: judge.provider.clone(),
+                        model: judge.model.clone(),
+                        messages: judge_messages(judge, &request.messages, &candidates),
+                        temperature: Some(0.0),
+                        cancel: request.cancel.clone(),
+                        skip_tools: true,
+                        ..Default::default()
+                    }).await?
+                    .content
+                    .unwrap_or_default();
+
+                // Без номера в вердикте вариант выбирается по logprobs, если провайдер их вернул,
+                // иначе запрос завершается ошибкой, а не молча отдаёт первый вариант
+                match parse_verdict(&verdict, candidates.len()) {
+                    Some(selected) => selected,
+                    None => {
+                        println!("Не удалось разобрать вердикт судьи {}/{}: {}", judge.provider, judge.model, verdict);
+                        let selected = best_by_score(&candidates).map_err(|_| anyhow::anyhow!(
+                            "Судья {}/{} не выбрал вариант: {}", judge.provider, judge.model, verdict
+                        ))?;
+                        println!("Вариант {} выбран по logprobs", selected);
+                        selected
+                    },
+                }
+            },
+        };
+
+        Ok(ServiceChatResponse {
+            content: candidates[selected].content.clone(),
+            attachments: candidates[selected].attachments.clone(),
+            metadata: Some(ResponseMetadata { selected: Some(selected as u32), ..Default::default() }),
+            usage: Some(usage),
+            choices: candidates,
+            ..Default::default()
+        })
+    }
+
+    pub async fn chat_stream(
+        &self,
+        mut request: ServiceChatRequest,
+    ) -> Result<ChatStream, Box<dyn std::error::Error>> {
+        if request.retrieval.is_some() {
+            return Err(anyhow::anyhow!("retrieval не поддерживается в потоковом режиме").into());
+        }
+        if request.best_of.is_some() || request.sampling.n.is_some_and(|n| n > 1) {
+            return Err(anyhow::anyhow!("n и best_of не поддерживаются в потоковом режиме").into());
+        }
+        self.resolve(&mut request)?;
+        self.negotiate(&mut request)?;
+
+        let request_id = request.request_id
+            .get_or_insert_with(|| Uuid::new_v4().to_string())
+            .clone();
+        let guard = self.requests.begin(&request_id, request.client_key.as_deref(), &request.provider, &request.model)?;
+        request.cancel = guard.token();
+
+        let stream = match self.service(&request.provider) {
+            Ok(service) => service.chat_stream(request).await,
+            Err(e) => Err(e.into()),
+        };
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                guard.finish(RequestStatus::Failed, None);
+                return Err(e);
+            }
+        };
+
+        // Guard живёт вместе с потоком: если клиент бросит поток, запрос будет отменён
+        Ok(futures::stream::unfold((stream, Some(guard)), |(mut stream, mut guard)| async move {
+            let event = stream.next().await?;
+            match &event {
+                Ok(ChatEvent::Done { usage, .. }) => if let Some(guard) = guard.take() {
+                    guard.finish(RequestStatus::Completed, usage.as_ref());
+                },
+                Err(_) => if let Some(guard) = guard.take() {
+                    guard.finish(RequestStatus::Failed, None);
+                },
+                _ => {},
+            }
+            Some((event, (stream, guard)))
+        }).boxed())
+    }
+
+    // Подставляет шаблон промпта, алиас модели и значения по умолчанию до выбора провайдера
+    fn resolve(&self, request: &mut ServiceChatRequest) -> anyhow::Result<()> {
+        if let Some(reference) = &request.template {
+            let template = self.prompts.get(reference)?;
+
+            let mut messages = template.render(&request.variables)?;
+            messages.append(&mut request.messages);
+            request.messages = messages;
+
+            if request.provider.is_empty() {
+                request.provider = template.provider.clone().unwrap_or_default();
+            }
+            if request.model.is_empty() {
+                request.model = template.model.clone().unwrap_or_default();
+            }
+            if request.temperature.is_none() {
+                request.temperature = template.temperature;
+            }
+        }
+
+        if request.provider.is_empty() && self.config().routes.contains_key(&request.model) {
+            let mut targets = self.route(request)?.into_iter();
+            let primary = targets.next().ok_or_else(|| anyhow::anyhow!("Нет доступных целей маршрута"))?;
+
+            request.route = Some(std::mem::take(&mut request.model));
+            request.provider = primary.provider;
+            request.model = primary.model;
+            request.fallback = targets.collect();
+        }
+
+        if request.provider.is_empty() {
+            let alias = self.alias(&request.model)?;
+
+            request.provider = alias.target.provider.clone();
+            request.model = alias.target.model.clone();
+            if request.temperature.is_none() {
+                request.temperature = alias.temperature;
+            }
+            request.sampling.fill_from(&alias.sampling);
+            request.fallback = alias.fallback.clone();
+        }
+
+        request.temperature.get_or_insert_with(default_temperature);
+
+        if request.provider.is_empty() || request.model.is_empty() {
+            return Err(anyhow::anyhow!("Не указаны provider и model"));
+        }
+        if request.messages.is_empty() {
+            return Err(anyhow::anyhow!("Не указаны messages"));
+        }
+
+        Ok(())
+    }
+
+    // Эксперимент выбирается по модели, которую запросил клиент, до разрешения алиасов и маршрутов
+    fn assign_experiment(&self, request: &mut ServiceChatRequest) -> Option<Assignment> {
+        let config = self.config();
+        let (name, experiment) = config.experiments
+            .iter()
+            .filter(|(_, experiment)| experiment.matches(&request.provider, &request.model))
+            .min_by_key(|(name, _)| *name)?;
+
+        // Без ключа клиента запросы распределяются случайно
+        let key = request.client_key.as_deref().or(request.request_id.as_deref()).unwrap_or_default();
+        let candidate = experiment.assign(name, key);
+        if experiment.mode == ExperimentMode::Split && candidate {
+            request.provider = experiment.candidate.provider.clone();
+            request.model = experiment.candidate.model.clone();
+        }
+
+        Some(Assignment {
+            experiment: name.clone(),
+            mode: experiment.mode,
+            candidate,
+            target: experiment.candidate.clone(),
+            client_key: request.client_key.clone(),
+        })
+    }
+
+    fn track_experiment(
+        &self,
+        assignment: Assignment,
+        request: &ServiceChatRequest,
+        latency: Duration,
+        result: &Result<ServiceChatResponse, Box<dyn std::error::Error>>,
+    ) {
+        if result.as_ref().is_err_and(|e| e.is::<Cancelled>()) {
+            return;
+        }
+        let arm = ArmResult::new(&request.provider, &request.model, latency, result);
+        let request_id = request.request_id.clone().unwrap_or_default();
+
+        match assignment.mode {
+            ExperimentMode::Split => self.experiment_log.record(ExperimentRecord {
+                experiment: assignment.experiment,
+                mode: assignment.mode,
+                request_id,
+                client_key: assignment.client_key,
+                messages: request.messages.clone(),
+                control: (!assignment.candidate).then(|| arm.clone()),
+                candidate: assignment.candidate.then_some(arm),
+                recorded_at: Utc::now(),
+            }),
+            ExperimentMode::Shadow if assignment.candidate => {
+                let mut shadow = request.clone();
+                shadow.provider = assignment.target.provider;
+                shadow.model = assignment.target.model;
+                shadow.route = None;
+                shadow.request_id = Some(format!("{}:shadow", request_id));
+                shadow.skip_tools = true;
+
+                let job = ShadowJob {
+                    experiment: assignment.experiment,
+                    client_key: assignment.client_key,
+                    request_id,
+                    request: shadow,
+                    control: arm,
+                };
+                if self.shadow.try_send(job).is_err() {
+                    println!("Очередь теневых запросов переполнена, копия запроса {} пропущена", request.request_id.as_deref().unwrap_or_default());
+                }
+            },
+            ExperimentMode::Shadow => {},
+        }
+    }
+
+    // Копия запроса уходит кандидату независимо от клиента: свой токен отмены и свой учёт
+    async fn shadow(&self, job: ShadowJob) {
+        let ShadowJob { experiment, client_key, request_id, mut request, control } = job;
+
+        let guard = match self.requests.begin(
+            request.request_id.as_deref().unwrap_or_default(),
+            None,
+            &request.provider,
+            &request.model,
+        ) {
+            Ok(guard) => guard,
+            Err(e) => {
+                println!("Теневой запрос эксперимента {} пропущен: {}", experiment, e);
+                return;
+            },
+        };
+        request.cancel = guard.token();
+
+        let started = Instant::now();
+        let result = match self.negotiate(&mut request) {
+            Ok(_) => self.dispatch_chat(request.clone()).await,
+            Err(e) => Err(e.into()),
+        };
+        let latency = started.elapsed();
+        self.observe(&request, latency, &result);
+
+        match &result {
+            Ok(response) => guard.finish(RequestStatus::Completed, response.usage.as_ref()),
+            Err(e) => {
+                println!("Ошибка теневого запроса {}/{}: {}", request.provider, request.model, e);
+                guard.finish(RequestStatus::Failed, None);
+            },
+        }
+
+        self.experiment_log.record(ExperimentRecord {
+            experiment,
+            mode: ExperimentMode::Shadow,
+            request_id,
+            client_key,
+            messages: request.messages.clone(),
+            control: Some(control),
+            candidate: Some(ArmResult::new(&request.provider, &request.model, latency, &result)),
+            recorded_at: Utc::now(),
+        });
+    }
+
+    pub fn start_experiments(self: &Arc<Self>) {
+        let Some(mut queue) = self.shadow_queue.lock().unwrap().take() else {
+            return;
+        };
+        let provider = Arc::downgrade(self);
+        let permits = Arc::new(Semaphore::new(SHADOW_CONCURRENCY));
+
+        shutdown::spawn(async move {
+            loop {
+                // Копии, которые ещё в очереди при остановке, не отправляются
+                let job = tokio::select! {
+                    _ = shutdown::cancelled() => break,
+                    job = queue.recv() => job,
+                };
+                let Some(job) = job else {
+                    break;
+                };
+                let Ok(permit) = permits.clone().acquire_owned().await else {
+                    break;
+                };
+                let Some(provider) = provider.upgrade() else {
+                    break;
+                };
+                shutdown::spawn(async move {
+                    provider.shadow(job).await;
+                    drop(permit);
+                });
+            }
+        });
+    }
+
+    pub fn experiments(&self) -> Vec<ExperimentInfo> {
+        let mut experiments = self.config()
+            .experiments
+            .iter()
+            .map(|(name, experiment)| ExperimentInfo {
+                name: name.clone(),
+                model: experiment.model.clone(),
+                candidate: format!("{}/{}", experiment.candidate.provider, experiment.candidate.model),
+                mode: experiment.mode,
+                percent: experiment.percent,
+                records: self.experiment_log.count(name),
+            })
+            .collect::<Vec<_>>();
+        experiments.sort_by(|a, b| a.name.cmp(&b.name));
+        experiments
+    }
+
+    pub fn export_experiment(&self, name: &str) -> anyhow::Result<Vec<ExperimentRecord>> {
+        if !self.config().experiments.contains_key(name) {
+            return Err(anyhow::anyhow!("Эксперимент - {} - не найден", name));
+        }
+        self.experiment_log.export(name)
+    }
+
+    fn route(&self, request: &ServiceChatRequest) -> anyhow::Result<Vec<AliasTarget>> {
+        let config = self.config();
+        let route = config.routes
+            .get(&request.model)
+            .ok_or_else(|| anyhow::anyhow!("Маршрут - {} - не найден", request.model))?;
+        let active = self.providers.active();
+        let candidates = route.targets
+            .iter()
+            .filter(|target| active.contains(&target.target.provider.to_uppercase()))
+            .map(|target| Candidate {
+                target,
+                capabilities: self.catalog.capabilities(&target.target.provider, &target.target.model),
+            })
+            .collect();
+
+        self.router.rank(
+            route.policy,
+            candidates,
+            estimate_tokens(&request.messages),
+            request.sampling.max_tokens,
+        )
+    }
+
+    // Ошибки формата ответа и попадания в кэш не говорят о состоянии цели
+    fn observe(
+        &self,
+        request: &ServiceChatRequest,
+        latency: Duration,
+        result: &Result<ServiceChatResponse, Box<dyn std::error::Error>>,
+    ) {
+        let success = match result {
+            Ok(response) if response.semantic_cache_hit => return,
+            Ok(_) => true,
+            // Ошибки запроса не говорят о состоянии провайдера
+            Err(e) if !should_fall_back(e.as_ref()) => return,
+            Err(_) => false,
+        };
+        self.router.record(&request.provider, &request.model, latency, success);
+    }
+
+    pub fn routing_stats(&self) -> Vec<TargetStats> {
+        self.router.stats()
+    }
+
+    fn alias(&self, name: &str) -> anyhow::Result<ModelAlias> {
+        if name.is_empty() {
+            return Err(anyhow::anyhow!("Не указаны provider и model"));
+        }
+        self.config()
+            .aliases
+            .get(name)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Алиас - {} - не найден", name))
+    }
+
+    // Убирает параметры сэмплирования, которые провайдер не поддерживает,
+    // или отклоняет запрос целиком в строгом режиме
+    fn negotiate(&self, request: &mut ServiceChatRequest) -> anyhow::Result<Vec<String>> {
+        let service = self.service(&request.provider)?;
+
+        let mut sampling = request.sampling.clone();
+        let dropped = sampling.retain(|name| service.supports_param(name));
+        if dropped.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        if self.params_strict.load(Ordering::Relaxed) {
+            return Err(anyhow::anyhow!(
+                "Параметры {} не поддерживаются провайдером {}",
+                dropped.join(", "), request.provider.to_uppercase()
+            ));
+        }
+
+        println!(
+            "Параметры {} не поддерживаются провайдером {} и будут пропущены",
+            dropped.join(", "), request.provider.to_uppercase()
+        );
+        request.sampling = sampling;
+
+        Ok(dropped.into_iter().map(str::to_string).collect())
+    }
+
+    fn publish_attachments(&self, response: &mut ServiceChatResponse, format: AttachmentFormat) {
+        // Вложения выбранного ответа совпадают с одним из вариантов, поэтому публикуются один раз
+        for attachment in &mut response.attachments {
+            self.attachments.publish(attachment, format);
+        }
+        for choice in &mut response.choices {
+            for attachment in &mut choice.attachments {
+                match response.attachments.iter().find(|published| published.file_id == attachment.file_id) {
+                    Some(published) => *attachment = published.clone(),
+                    None => self.attachments.publish(attachment, format),
+                }
+            }
+        }
+    }
+
+    pub fn attachment(&self, token: &str) -> Option<(String, Vec<u8>)> {
+        self.attachments.get(token)
+    }
+
+    pub async fn files(&self, provider: &str) -> Result<Vec<UploadedFile>, Box<dyn std::error::Error>> {
+        self.service(provider)?.files().await
+    }
+
+    pub async fn file(&self, provider: &str, id: &str) -> Result<UploadedFile, Box<dyn std::error::Error>> {
+        self.service(provider)?.file(id).await
+    }
+
+    pub async fn file_content(&self, provider: &str, id: &str) -> Result<Attachment, Box<dyn std::error::Error>> {
+        self.service(provider)?.file_content(id).await
+    }
+
+    pub async fn delete_file(&self, provider: &str, id: &str) -> Result<bool, Box<dyn std::error::Error>> {
+        self.service(provider)?.delete_file(id).await
+    }
+
+    pub async fn upload_file(
+        &self,
+        provider: &str,
+        file: FileUpload,
+    ) -> Result<UploadedFile, Box<dyn std::error::Error>> {
+        self.service(provider)?.upload(file).await
+    }
+
+    pub async fn models(&self) -> ModelCatalogSnapshot {
+        if self.catalog.needs_refresh() {
+            self.refresh_models().await;
+        }
+        self.catalog.snapshot(&self.providers.active())
+    }
+
+    // Каталог считается обновлённым, если ответил хотя бы один провайдер
+    async fn refresh_models(&self) {
+        self.catalog.mark_attempted();
+
+        let services = self.providers.services();
+        let mut refreshed = services.is_empty();
+        for (name, service) in services {
+            match service.models().await {
+                Ok(models) => {
+                    refreshed = true;
+                    self.catalog.update(&name, models
+                    .into_iter()
+                    .map(|model| ModelInfo {
+                        provider: name.clone(),
+                        capabilities: service.model_capabilities(&model),
+                        id: model.id,
+                        owned_by: model.owned_by,
+                    })
+                    .collect());
+                },
+                Err(e) => println!("Не удалось получить список моделей {}: {}", name, e),
+            }
+        }
+        if refreshed {
+            self.catalog.mark_refreshed();
+        }
+    }
+
+    pub fn start_catalog_refresh(self: &Arc<Self>) {
+        let provider = Arc::downgrade(self);
+        let interval = models_refresh_interval();
+
+        shutdown::spawn(async move {
+            loop {
+                let Some(provider) = provider.upgrade() else {
+                    break;
+                };
+                provider.refresh_models().await;
+                drop(provider);
+
+                if shutdown::sleep(interval).await {
+                    break;
+                }
+            }
+        });
+    }
+
+    // С `ping` каждый провайдер дополнительно проверяется запросом списка моделей
+    pub async fn provider_statuses(&self, ping: bool) -> Vec<ProviderStatus> {
+        let stats = self.router.stats();
+
+        futures::future::join_all(self.providers.list().into_iter().map(|(name, entry)| {
+            let targets = stats
+                .iter()
+                .filter(|target| target.provider == name)
+                .cloned()
+                .collect::<Vec<_>>();
+
+            async move {
+                let health = entry.service.health();
+                let ping = if ping {
+                    Some(ping_service(entry.service.as_ref()).await)
+                } else {
+                    None
+                };
+
+                ProviderStatus {
+                    healthy: targets.iter().all(|target| target.circuit != CircuitState::Open)
+                        && health.token.as_ref().is_none_or(|token| token.valid),
+                    inflight: self.requests.inflight(&name),
+                    last_success: targets.iter().filter_map(|target| target.last_success).max(),
+                    last_failure: targets.iter().filter_map(|target| target.last_failure).max(),
+                    name,
+                    kind: entry.kind,
+                    draining: entry.draining,
+                    token: health.token,
+                    tools: health.tools,
+                    ping,
+                    targets,
+                }
+            }
+        })).await
+    }
+
+    // Причины, по которым шлюз не готов принимать запросы; пустой список означает готовность
+    pub fn readiness(&self) -> Vec<String> {
+        if shutdown::is_stopping() {
+            return vec!["Шлюз останавливается".to_string()];
+        }
+
+        let services = self.providers.services();
+        if services.is_empty() {
+            return vec!["Нет ни одного доступного провайдера".to_string()];
+        }
+
+        services
+            .iter()
+            .filter(|(_, service)| !service.health().tools.loaded)
+            .map(|(name, _)| format!("Инструменты провайдера {} не загружены", name))
+            .collect()
+    }
+
+    pub async fn status(&self, ping: bool) -> GatewayStatus {
+        let problems = self.readiness();
+
+        GatewayStatus {
+            ready: problems.is_empty(),
+            problems,
+            config: self.config_status(),
+            providers: self.provider_statuses(ping).await,
+        }
+    }
+
+    // Новый сервис проходит авторизацию до замены, при ошибке остаётся прежний
+    pub async fn put_provider(&self, name: &str, update: ProviderUpdate) -> anyhow::Result<bool> {
+        let kind = update.kind.unwrap_or_else(|| name.to_string());
+        let data = ModelData { token: update.token, scope: update.scope, network: update.network };
+        let model = Model::from_kind(&kind, data)
+            .ok_or_else(|| anyhow::anyhow!("Неизвестный тип провайдера - {}", kind))?;
+        let service = model
+            .get_service().await
+            .ok_or_else(|| anyhow::anyhow!("Невозможно получить сервис {}", name.to_uppercase()))?;
+
+        let replaced = self.providers.insert(name, &model.to_string(), Arc::from(service));
+        println!("Провайдер {} {}", name.to_uppercase(), if replaced { "обновлён" } else { "добавлен" });
+
+        Ok(replaced)
+    }
+
+    pub fn remove_provider(&self, name: &str) -> bool {
+        let removed = self.providers.remove(name);
+        if removed {
+            println!("Провайдер {} удалён, запросов в работе: {}", name.to_uppercase(), self.requests.inflight(name));
+        }
+        removed
+    }
+
+    // Возвращает число запросов, которые провайдер ещё обрабатывает
+    pub fn drain_provider(&self, name: &str, draining: bool) -> Option<usize> {
+        if !self.providers.set_draining(name, draining) {
+            return None;
+        }
+
+        let inflight = self.requests.inflight(name);
+        if draining {
+            println!("Провайдер {} выводится из работы, запросов в работе: {}", name.to_uppercase(), inflight);
+        } else {
+            println!("Провайдер {} снова принимает запросы", name.to_uppercase());
+        }
+        Some(inflight)
+    }
+
+    // Перечитывает файл конфигурации и `.env`. Новая конфигурация применяется целиком
+    // или не применяется вовсе: при ошибке остаётся прежняя. Возвращает true, если она сменилась
+    pub async fn reload_config(&self, force: bool) -> anyhow::Result<bool> {
+        let mut active = self.config_source.lock().await;
+        let source = ConfigSource::read(&config_path(), &env_path())
+            .inspect_err(|e| println!("Не удалось перечитать конфигурацию: {}", e))?;
+        let hash = source.hash();
+
+        {
+            let status = self.config_status.read().unwrap();
+            let rejected = status.rejected.as_ref().is_some_and(|rejected| rejected.hash == hash);
+            if !force && (status.hash == hash || rejected) {
+                return Ok(false);
+            }
+        }
+
+        let diff = diff_lines(&active.text, &source.text);
+        let changed_env = source.changed_env(&active);
+
+        let (config, services) = match self.prepare_config(&source, &changed_env).await {
+            Ok(prepared) => prepared,
+            Err(e) => {
+                println!("Новая конфигурация {} отклонена, действует прежняя: {}", hash, e);
+                log_config_diff(&diff, &changed_env);
+                self.config_status.write().unwrap().rejected = Some(RejectedConfig {
+                    hash,
+                    error: e.to_string(),
+                    rejected_at: Utc::now(),
+                });
+                return Err(e);
+            },
+        };
+
+        // Окружение процесса не меняется: настройки читаются из прочитанного `.env`
+        for (name, service) in services {
+            match service {
+                Some(service) => {
+                    self.providers.insert_from_env(&name, &name, service);
+                },
+                None => if self.providers.remove_from_env(&name) {
+                    println!("Провайдер {} удалён: его токена больше нет в .env", name);
+                },
+            }
+        }
+        let var = |name: &str| source.var(&self.startup_source, name);
+        self.params_strict.store(params_strict_from(var), Ordering::Relaxed);
+        self.structured_output_retries.store(structured_output_retries_from(var), Ordering::Relaxed);
+        *self.rag_template.write().unwrap() = rag_template_from(var);
+        self.catalog.set_overrides(config.models.clone());
+        *self.config.write().unwrap() = Arc::new(config);
+        if let Err(e) = self.prompts.load() {
+            println!("Не удалось перезагрузить шаблоны промптов: {}", e);
+        }
+
+        {
+            let mut status = self.config_status.write().unwrap();
+            status.version += 1;
+            status.hash = hash;
+            status.loaded_at = Utc::now();
+            status.rejected = None;
+            println!("Конфигурация перезагружена, версия {} ({})", status.version, status.hash);
+        }
+        log_config_diff(&diff, &changed_env);
+        *active = source;
+
+        Ok(true)
+    }
+
+    // Разбирает конфигурацию и заранее создаёт сервисы провайдеров с новыми ключами,
+    // чтобы ошибка авторизации отклонила конфигурацию до того, как что-то изменится.
+    // None означает, что токен провайдера убран и провайдер нужно удалить
+    async fn prepare_config(
+        &self,
+        source: &ConfigSource,
+        changed_env: &[String],
+    ) -> anyhow::Result<(GatewayConfig, Vec<(String, Option<Arc<dyn LLMService>>)>)> {
+        let config = GatewayConfig::parse(&source.text)?;
+
+        let mut services = Vec::new();
+        for mut model in Model::all() {
+            let name = model.to_string().to_uppercase();
+            if !PROVIDER_ENV.iter().any(|prefix| changed_env.contains(&format!("{}_{}", prefix, name))) {
+                continue;
+            }
+            // Провайдер, добавленный через API, перезагрузка `.env` не трогает
+            if self.providers.is_from_env(&name) == Some(false) {
+                println!("Провайдер {} добавлен через API, изменения .env для него не применяются", name);
+                continue;
+            }
+            let Some(data) = ModelData::from_env(&name, |key| source.var(&self.startup_source, key)) else {
+                services.push((name, None));
+                continue;
+            };
+
+            model.set_data(data);
+            let service = model
+                .get_service().await
+                .ok_or_else(|| anyhow::anyhow!("Невозможно получить сервис {}", name))?;
+            services.push((name, Some(Arc::from(service))));
+        }
+
+        Ok((config, services))
+    }
+
+    pub fn start_config_watcher(self: &Arc<Self>) {
+        let provider = Arc::downgrade(self);
+        let interval = config_watch_interval();
+
+        shutdown::spawn(async move {
+            while !shutdown::sleep(interval).await {
+                let Some(provider) = provider.upgrade() else {
+                    break;
+                };
+                // Ошибки уже записаны в журнал, прежняя конфигурация продолжает действовать
+                let _ = provider.reload_config(false).await;
+            }
+        });
+    }
+
+    pub fn config_status(&self) -> ConfigStatus {
+        self.config_status.read().unwrap().clone()
+    }
+
+    pub fn prompts(&self) -> Vec<PromptTemplateInfo> {
+        self.prompts.list()
+    }
+
+    pub fn cancel(&self, request_id: &str) -> bool {
+        self.requests.cancel(request_id)
+    }
+
+    pub fn cancel_owned(&self, request_id: &str, client_key: &str) -> bool {
+        self.requests.cancel_owned(request_id, client_key)
+    }
+
+    // Отменяет все запросы в работе, возвращает их число
+    pub fn cancel_all(&self) -> usize {
+        self.requests.cancel_all()
+    }
+
+    // Дожидается завершения всех запросов, включая потоковые и теневые
+    pub async fn wait_idle(&self) {
+        while self.requests.inflight_total() > 0 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    pub fn inflight_total(&self) -> usize {
+        self.requests.inflight_total()
+    }
+
+    // Ждёт, пока у провайдера не останется запросов в работе
+    pub async fn wait_drained(&self, name: &str) {
+        while self.requests.inflight(name) > 0 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    pub fn inflight(&self, name: &str) -> usize {
+        self.requests.inflight(name)
+    }
+
+    // Сбрасывает на диск журналы использования и экспериментов
+    pub fn flush(&self) {
+        if let Err(e) = self.requests.usage().flush() {
+            println!("Не удалось сохранить журнал использования: {}", e);
+        }
+        if let Err(e) = self.experiment_log.flush() {
+            println!("Не удалось сохранить журнал экспериментов: {}", e);
+        }
+    }
+
+    pub fn usage(&self) -> Vec<UsageRecord> {
+        self.requests.usage().recent()
+    }
+
+    fn service(&self, provider: &str) -> Result<Arc<dyn LLMService>, Unavailable> {
+        self.providers.get(provider)
+    }
+
+    fn config(&self) -> Arc<GatewayConfig> {
+        self.config.read().unwrap().clone()
+    }
+
+    async fn retrieve(
+        &self,
+        messages: &mut [ChatMessage],
+        retrieval: RetrievalOptions,
+    ) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let message = messages
+            .iter_mut()
+            .rev()
+            .find(|message| message.role == "user")
+            .ok_or_else(|| anyhow::anyhow!("Для поиска контекста нужно сообщение пользователя"))?;
+        let question = message.text_content().unwrap_or_default();
+
+        let hits = self.query_collection(&retrieval.collection, CollectionQueryRequest {
+            text: Some(question.clone()),
+            vector: None,
+            top_k: retrieval.top_k,
+            filter: retrieval.filter,
+        }).await?.hits;
+
+        let context = hits
+            .iter()
+            .map(|hit| format!("[{}] {}", hit.id, hit.text.as_deref().unwrap_or_default()))
+            .collect::<Vec<_>>()
+            .join("\n\n");
+
+        let template = retrieval.template
+            .clone()
+            .unwrap_or_else(|| self.rag_template.read().unwrap().clone());
+        let prompt = fill_rag_template(&template, &context, &question);
+        message.content = message.content.take().map(|content| content.with_text(prompt));
+
+        Ok(hits.into_iter().map(|hit| hit.id).collect())
+    }
+
+    async fn cached_chat(
         &self,
         request: ServiceChatRequest,
     ) -> Result<ServiceChatResponse, Box<dyn std::error::Error>> {
-        self.providers
-            .get(&request.provider.to_uppercase())
-            .ok_or_else(|| anyhow::anyhow!(
-                "Модель - {} - не поддерживается", request.provider.to_uppercase()
-            ))?
-            .chat(request).await
+        let service = self.service(&request.provider)?;
+
+        let Some(cache) = &self.semantic_cache else {
+            return service.chat(request).await;
+        };
+
+        let scope = SemanticCache::scope(&request, &service.health().tools);
+        let embedding = match SemanticCache::last_user_message(&request.messages) {
+            Some(input) => match self.embedding(ServiceEmbeddingRequest {
+                provider: cache.provider.clone(),
+                model: cache.model.clone(),
+                input: EmbeddingInput::Single(input),
+                options: EmbeddingOptions::default(),
+            }).await {
+                Ok(response) => Some(response.content),
+                Err(e) => {
+                    println!("Семантический кэш недоступен: {}", e);
+                    None
+                }
+            },
+            None => None,
+        };
+
+        if let Some(embedding) = &embedding {
+            if let Some(content) = cache.lookup(scope, embedding) {
+                return Ok(ServiceChatResponse { content, semantic_cache_hit: true, ..Default::default() });
+            }
+        }
+
+        let response = service.chat(request).await?;
+        // Кэш хранит только текст, ответы с вложениями не сохраняются
+        if let Some(embedding) = embedding.filter(|_| response.attachments.is_empty()) {
+            cache.insert(scope, embedding, response.content.clone());
+        }
+
+        Ok(response)
     }
 
     pub async fn embedding(
         &self,
-        request: ServiceEmbeddingRequest,
+        mut request: ServiceEmbeddingRequest,
     ) -> Result<ServiceEmbeddingResponse, Box<dyn std::error::Error>> {
-        self.providers
-            .get(&request.provider.to_uppercase())
-            .ok_or_else(|| anyhow::anyhow!(
-                "Модель - {} - не поддерживается", request.provider.to_uppercase()
-            ))?
-            .embedded(request).await
+        // Векторы разных моделей несовместимы, поэтому резервные цели алиаса здесь не используются
+        if request.provider.is_empty() {
+            let alias = self.alias(&request.model)?;
+            request.provider = alias.target.provider.clone();
+            request.model = alias.target.model.clone();
+        }
+
+        let options = request.options.clone();
+        let mut response = self.service(&request.provider)?
+            .embedded(request).await?;
+
+        options.apply(&mut response.content);
+        response.embeddings.iter_mut().for_each(|embedding| options.apply(embedding));
+
+        Ok(response)
+    }
+
+    pub fn collections(&self) -> Vec<CollectionInfo> {
+        self.store.list()
+    }
+
+    pub async fn create_collection(
+        &self,
+        name: &str,
+        settings: CollectionSettings,
+    ) -> anyhow::Result<CollectionInfo> {
+        self.store.create(name, settings).await
+    }
+
+    pub async fn remove_collection(&self, name: &str) -> anyhow::Result<bool> {
+        self.store.remove(name).await
+    }
+
+    pub async fn upsert_documents(
+        &self,
+        name: &str,
+        request: CollectionUpsertRequest,
+    ) -> Result<CollectionUpsertResponse, Box<dyn std::error::Error>> {
+        let collection = self.store
+            .get(name)
+            .ok_or_else(|| anyhow::anyhow!("Коллекция - {} - не найдена", name))?;
+        let settings = collection.read().unwrap().settings.clone();
+
+        let mut documents = request.documents;
+        let missing = documents
+            .iter()
+            .enumerate()
+            .filter(|(_, document)| document.vector.is_none())
+            .map(|(index, document)| match &document.document.text {
+                Some(text) => Ok((index, text.clone())),
+                None => Err(anyhow::anyhow!(
+                    "Документ {} не содержит ни текста, ни вектора", document.document.id
+                )),
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        if !missing.is_empty() {
+            let (indices, input): (Vec<_>, Vec<_>) = missing.into_iter().unzip();
+            let response = self.embedding(ServiceEmbeddingRequest {
+                provider: settings.provider,
+                model: settings.model,
+                input: EmbeddingInput::Batch(input),
+                options: EmbeddingOptions::default(),
+            }).await?;
+
+            for (index, embedding) in indices.into_iter().zip(response.embeddings) {
+                documents[index].vector = Some(embedding);
+            }
+        }
+
+        let upserted = documents.len();
+        collection.write().unwrap().upsert_batch(documents
+            .into_iter()
+            .map(|UpsertDocument { document, vector }| (document, vector.unwrap_or_default()))
+            .collect())?;
+        self.store.persist(name).await?;
+
+        Ok(CollectionUpsertResponse { upserted })
+    }
+
+    pub async fn delete_document(&self, name: &str, id: &str) -> anyhow::Result<bool> {
+        let collection = self.store
+            .get(name)
+            .ok_or_else(|| anyhow::anyhow!("Коллекция - {} - не найдена", name))?;
+
+        let deleted = collection.write().unwrap().delete(id);
+        if deleted {
+            self.store.persist(name).await?;
+        }
+
+        Ok(deleted)
+    }
+
+    pub async fn query_collection(
+        &self,
+        name: &str,
+        request: CollectionQueryRequest,
+    ) -> Result<CollectionQueryResponse, Box<dyn std::error::Error>> {
+        let collection = self.store
+            .get(name)
+            .ok_or_else(|| anyhow::anyhow!("Коллекция - {} - не найдена", name))?;
+        let settings = collection.read().unwrap().settings.clone();
+
+        let query = match (request.vector, request.text) {
+            (Some(vector), _) => vector,
+            (None, Some(text)) => self.embedding(ServiceEmbeddingRequest {
+                provider: settings.provider,
+                model: settings.model,
+                input: EmbeddingInput::Single(text),
+                options: EmbeddingOptions::default(),
+            }).await?.content,
+            (None, None) => return Err(anyhow::anyhow!("Запрос должен содержать text или vector").into()),
+        };
+
+        let hits = collection.read().unwrap().search(&query, request.top_k, &request.filter)?;
+
+        Ok(CollectionQueryResponse { hits })
+    }
+}
+
+fn log_config_diff(diff: &[String], changed_env: &[String]) {
+    for line in diff {
+        println!("    {}", line);
+    }
+    if !changed_env.is_empty() {
+        println!("    Изменены переменные окружения: {}", changed_env.join(", "));
+    }
+}
+
+// Подстановка за один проход: `{question}` внутри найденных документов
+// и `{context}` внутри вопроса остаются текстом
+fn fill_rag_template(template: &str, context: &str, question: &str) -> String {
+    let mut result = String::with_capacity(template.len() + context.len() + question.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(after) = rest.strip_prefix("{context}") {
+            result.push_str(context);
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("{question}") {
+            result.push_str(question);
+            rest = after;
+        } else {
+            result.push('{');
+            rest = &rest[1..];
+        }
+    }
+    result.push_str(rest);
+
+    result
+}
+
+async fn ping_service(service: &dyn LLMService) -> PingResult {
+    let started = Instant::now();
+    let error = match tokio::time::timeout(PING_TIMEOUT, service.models()).await {
+        Ok(Ok(_)) => None,
+        Ok(Err(e)) => Some(e.to_string()),
+        Err(_) => Some("Превышено время ожидания".to_string()),
+    };
+
+    PingResult {
+        ok: error.is_none(),
+        latency_ms: started.elapsed().as_millis() as u64,
+        error,
     }
 }
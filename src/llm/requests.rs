@@ -0,0 +1,200 @@
+use std::collections::hash_map::{Entry, HashMap};
+use std::fmt;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use chrono::Utc;
+use tokio_util::sync::CancellationToken;
+
+use crate::llm::usage::{RequestStatus, UsageRecord, UsageTracker};
+use crate::llm::Usage;
+
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Запрос отменён")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Запрос с таким идентификатором уже выполняется.
+#[derive(Debug)]
+pub struct DuplicateRequest(pub String);
+
+impl fmt::Display for DuplicateRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Запрос {} уже выполняется", self.0)
+    }
+}
+
+impl std::error::Error for DuplicateRequest {}
+
+pub async fn cancellable<F: Future>(cancel: &CancellationToken, future: F) -> Result<F::Output, Cancelled> {
+    tokio::select! {
+        biased;
+        _ = cancel.cancelled() => Err(Cancelled),
+        output = future => Ok(output),
+    }
+}
+
+struct InflightRequest {
+    provider: String,
+    // Клиент, которому разрешено отменить запрос без административного токена
+    client_key: Option<String>,
+    token: CancellationToken,
+}
+
+/// Запросы в работе, доступные для отмены по идентификатору.
+pub struct RequestRegistry {
+    inflight: Mutex<HashMap<String, InflightRequest>>,
+    usage: UsageTracker,
+}
+
+impl RequestRegistry {
+    pub fn new(usage: UsageTracker) -> Self {
+        Self { inflight: Mutex::new(HashMap::new()), usage }
+    }
+
+    // Идентификатор задаёт клиент, поэтому второй запрос с идентификатором запроса
+    // в работе отклоняется: иначе он перехватил бы чужую отмену и учёт
+    pub fn begin(
+        self: &Arc<Self>,
+        request_id: &str,
+        client_key: Option<&str>,
+        provider: &str,
+        model: &str,
+    ) -> Result<RequestGuard, DuplicateRequest> {
+        let token = CancellationToken::new();
+        match self.inflight.lock().unwrap().entry(request_id.to_string()) {
+            Entry::Occupied(_) => return Err(DuplicateRequest(request_id.to_string())),
+            Entry::Vacant(entry) => {
+                entry.insert(InflightRequest {
+                    provider: provider.to_uppercase(),
+                    client_key: client_key.map(str::to_string),
+                    token: token.clone(),
+                });
+            },
+        }
+
+        Ok(RequestGuard {
+            registry: self.clone(),
+            request_id: request_id.to_string(),
+            provider: provider.to_string(),
+            model: model.to_string(),
+            started: Instant::now(),
+            token,
+            finished: false,
+        })
+    }
+
+    pub fn cancel(&self, request_id: &str) -> bool {
+        match self.inflight.lock().unwrap().get(request_id) {
+            Some(request) => {
+                request.token.cancel();
+                true
+            },
+            None => false,
+        }
+    }
+
+    // Отмена от клиента: только запроса, начатого с тем же ключом клиента
+    pub fn cancel_owned(&self, request_id: &str, client_key: &str) -> bool {
+        match self.inflight.lock().unwrap().get(request_id) {
+            Some(request) if request.client_key.as_deref() == Some(client_key) => {
+                request.token.cancel();
+                true
+            },
+            _ => false,
+        }
+    }
+
+    pub fn cancel_all(&self) -> usize {
+        let inflight = self.inflight.lock().unwrap();
+        for request in inflight.values() {
+            request.token.cancel();
+        }
+        inflight.len()
+    }
+
+    pub fn inflight_total(&self) -> usize {
+        self.inflight.lock().unwrap().len()
+    }
+
+    pub fn inflight(&self, provider: &str) -> usize {
+        self.inflight
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|request| request.provider == provider.to_uppercase())
+            .count()
+    }
+
+    pub fn usage(&self) -> &UsageTracker {
+        &self.usage
+    }
+}
+
+/// Живёт столько же, сколько запрос. Если запрос не был завершён явно
+/// (например, клиент отключился и future был сброшен), при удалении
+/// отменяет его токен и записывает запрос как отменённый.
+pub struct RequestGuard {
+    registry: Arc<RequestRegistry>,
+    request_id: String,
+    provider: String,
+    model: String,
+    started: Instant,
+    token: CancellationToken,
+    finished: bool,
+}
+
+impl RequestGuard {
+    pub fn token(&self) -> CancellationToken {
+        self.token.clone()
+    }
+
+    // Запрос ушёл на резервную модель, учёт ведётся по фактической цели
+    pub fn retarget(&mut self, provider: &str, model: &str) {
+        self.provider = provider.to_string();
+        self.model = model.to_string();
+        if let Some(request) = self.registry.inflight.lock().unwrap().get_mut(&self.request_id) {
+            request.provider = provider.to_uppercase();
+        }
+    }
+
+    pub fn finish(mut self, status: RequestStatus, usage: Option<&Usage>) {
+        self.record(status, usage);
+    }
+
+    fn record(&mut self, status: RequestStatus, usage: Option<&Usage>) {
+        self.finished = true;
+
+        if status == RequestStatus::Cancelled {
+            println!("Запрос {} отменён", self.request_id);
+        }
+
+        self.registry.usage.record(UsageRecord {
+            request_id: self.request_id.clone(),
+            provider: self.provider.clone(),
+            model: self.model.clone(),
+            status,
+            prompt_tokens: usage.map(|usage| usage.prompt_tokens).unwrap_or_default(),
+            completion_tokens: usage.map(|usage| usage.completion_tokens).unwrap_or_default(),
+            latency_ms: self.started.elapsed().as_millis() as u64,
+            finished_at: Utc::now(),
+        });
+    }
+}
+
+impl Drop for RequestGuard {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.token.cancel();
+            self.record(RequestStatus::Cancelled, None);
+        }
+        self.registry.inflight.lock().unwrap().remove(&self.request_id);
+    }
+}
@@ -0,0 +1,494 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use ringbuffer::{AllocRingBuffer, RingBuffer};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::config::file::AliasTarget;
+use crate::llm::catalog::ModelCapabilities;
+use crate::llm::requests::Cancelled;
+use crate::llm::schema::StructuredOutputError;
+use crate::llm::services::UpstreamError;
+use crate::llm::{ChatMessage, CIRCUIT_COOLDOWN, CIRCUIT_FAILURE_THRESHOLD, ROUTER_WINDOW};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RoutingPolicy {
+    // Самая дешёвая цель, в контекст которой помещается запрос
+    #[default]
+    CheapestThatFits,
+    Fastest,
+    Weighted,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RouteConfig {
+    #[serde(default)]
+    pub policy: RoutingPolicy,
+    pub targets: Vec<RouteTarget>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RouteTarget {
+    #[serde(flatten)]
+    pub target: AliasTarget,
+    #[serde(default = "default_weight")]
+    pub weight: f64,
+}
+fn default_weight() -> f64 { 1.0 }
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TargetStats {
+    pub provider: String,
+    pub model: String,
+    pub p50_ms: Option<u64>,
+    pub p95_ms: Option<u64>,
+    pub error_rate: f64,
+    pub circuit: CircuitState,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_success: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_failure: Option<DateTime<Utc>>,
+}
+
+struct TargetState {
+    latencies: AllocRingBuffer<u64>,
+    outcomes: AllocRingBuffer<bool>,
+    consecutive_failures: usize,
+    opened_at: Option<Instant>,
+    // В полуоткрытом состоянии пропускается один пробный запрос
+    probing: Option<Instant>,
+    last_success: Option<DateTime<Utc>>,
+    last_failure: Option<DateTime<Utc>>,
+}
+
+impl TargetState {
+    fn new() -> Self {
+        Self {
+            latencies: AllocRingBuffer::new(ROUTER_WINDOW),
+            outcomes: AllocRingBuffer::new(ROUTER_WINDOW),
+            consecutive_failures: 0,
+            opened_at: None,
+            probing: None,
+            last_success: None,
+            last_failure: None,
+        }
+    }
+
+    fn circuit(&self) -> CircuitState {
+        match self.opened_at {
+            None => CircuitState::Closed,
+            Some(opened_at) if opened_at.elapsed() < Duration::from_secs(CIRCUIT_COOLDOWN) => CircuitState::Open,
+            Some(_) => CircuitState::HalfOpen,
+        }
+    }
+
+    fn percentile(&self, percentile: f64) -> Option<u64> {
+        let mut latencies = self.latencies.to_vec();
+        if latencies.is_empty() {
+            return None;
+        }
+        latencies.sort_unstable();
+        let index = ((latencies.len() - 1) as f64 * percentile).round() as usize;
+        Some(latencies[index])
+    }
+
+    fn accepts(&self) -> bool {
+        match self.circuit() {
+            CircuitState::Closed => true,
+            CircuitState::Open => false,
+            // Пробный запрос мог быть отменён и не записан, поэтому ожидание ограничено
+            CircuitState::HalfOpen => self.probing
+                .is_none_or(|started| started.elapsed() >= Duration::from_secs(CIRCUIT_COOLDOWN)),
+        }
+    }
+
+    fn error_rate(&self) -> f64 {
+        if self.outcomes.is_empty() {
+            return 0.0;
+        }
+        self.outcomes.iter().filter(|success| !**success).count() as f64 / self.outcomes.len() as f64
+    }
+}
+
+/// Цель сейчас не принимает запросы: провайдер не подключён, выводится из работы или цепь разомкнута.
+#[derive(Debug)]
+pub struct Unavailable(pub String);
+
+impl fmt::Display for Unavailable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Unavailable {}
+
+// Переключаться на запасную цель стоит только при сбоях сети и провайдера. Ошибки самого
+// запроса повторятся и на другой цели, а инструменты при этом выполнятся ещё раз
+pub fn should_fall_back(error: &(dyn std::error::Error + 'static)) -> bool {
+    let mut current = Some(error);
+    while let Some(error) = current {
+        if let Some(error) = error.downcast_ref::<UpstreamError>() {
+            return error.retryable();
+        }
+        if error.is::<Unavailable>() || error.is::<reqwest::Error>() || error.is::<reqwest_middleware::Error>() {
+            return true;
+        }
+        current = error.source();
+    }
+    false
+}
+
+type SendError = Box<dyn std::error::Error + Send + Sync>;
+
+// Ошибку сервиса нельзя держать между await в параллельных запросах, потому что она не Send.
+// Известные типы переносятся как есть, чтобы `should_fall_back` и роутер их различали
+pub fn into_send(error: Box<dyn std::error::Error>) -> SendError {
+    fn take<T: std::error::Error + Send + Sync + 'static>(
+        error: Box<dyn std::error::Error>,
+    ) -> Result<SendError, Box<dyn std::error::Error>> {
+        error.downcast::<T>().map(|error| error as SendError)
+    }
+
+    take::<UpstreamError>(error)
+        .or_else(take::<Unavailable>)
+        .or_else(take::<Cancelled>)
+        .or_else(take::<StructuredOutputError>)
+        .or_else(take::<reqwest::Error>)
+        .or_else(take::<reqwest_middleware::Error>)
+        .unwrap_or_else(|error| error.to_string().into())
+}
+
+/// Сведения о цели, по которым выбирается маршрут.
+pub struct Candidate<'a> {
+    pub target: &'a RouteTarget,
+    pub capabilities: Option<ModelCapabilities>,
+}
+
+/// Статистика задержек и ошибок по целям и выбор цели для маршрута.
+pub struct Router {
+    targets: RwLock<HashMap<String, TargetState>>,
+}
+
+fn key(provider: &str, model: &str) -> String {
+    format!("{}/{}", provider.to_uppercase(), model)
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self { targets: RwLock::new(HashMap::new()) }
+    }
+
+    pub fn is_open(&self, provider: &str, model: &str) -> bool {
+        self.targets
+            .read()
+            .unwrap()
+            .get(&key(provider, model))
+            .is_some_and(|state| state.circuit() == CircuitState::Open)
+    }
+
+    pub fn record(&self, provider: &str, model: &str, latency: Duration, success: bool) {
+        let mut targets = self.targets.write().unwrap();
+        let state = targets.entry(key(provider, model)).or_insert_with(TargetState::new);
+
+        state.outcomes.enqueue(success);
+        state.probing = None;
+        if success {
+            state.last_success = Some(Utc::now());
+            state.latencies.enqueue(latency.as_millis() as u64);
+            state.consecutive_failures = 0;
+            state.opened_at = None;
+            return;
+        }
+
+        state.last_failure = Some(Utc::now());
+        state.consecutive_failures += 1;
+        let reopen = state.circuit() == CircuitState::HalfOpen;
+        if reopen || state.consecutive_failures >= CIRCUIT_FAILURE_THRESHOLD {
+            if state.circuit() != CircuitState::Open {
+                println!("Цепь {} разомкнута после {} ошибок", key(provider, model), state.consecutive_failures);
+            }
+            state.opened_at = Some(Instant::now());
+        }
+    }
+
+    pub fn stats(&self) -> Vec<TargetStats> {
+        let mut stats = self.targets
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(key, state)| {
+                let (provider, model) = key.split_once('/').unwrap_or((key, ""));
+                TargetStats {
+                    provider: provider.to_string(),
+                    model: model.to_string(),
+                    p50_ms: state.percentile(0.5),
+                    p95_ms: state.percentile(0.95),
+                    error_rate: state.error_rate(),
+                    circuit: state.circuit(),
+                    last_success: state.last_success,
+                    last_failure: state.last_failure,
+                }
+            })
+            .collect::<Vec<_>>();
+        stats.sort_by(|a, b| (&a.provider, &a.model).cmp(&(&b.provider, &b.model)));
+        stats
+    }
+
+    // Упорядочивает цели маршрута: первая получает запрос, остальные служат резервом
+    pub fn rank(
+        &self,
+        policy: RoutingPolicy,
+        candidates: Vec<Candidate>,
+        prompt_tokens: u32,
+        max_tokens: Option<u32>,
+    ) -> anyhow::Result<Vec<AliasTarget>> {
+        let mut targets = self.targets.write().unwrap();
+        // Без max_tokens длина ответа оценивается длиной промпта
+        let completion_tokens = max_tokens.unwrap_or(prompt_tokens);
+
+        let mut ranked = candidates
+            .into_iter()
+            .filter(|candidate| candidate.capabilities
+                .as_ref()
+                .and_then(|capabilities| capabilities.context_length)
+                // max_tokens приходит от клиента, поэтому сумма считается без переполнения
+                .is_none_or(|context_length| {
+                    prompt_tokens as u64 + max_tokens.unwrap_or(0) as u64 <= context_length as u64
+                })
+            )
+            .filter_map(|candidate| {
+                let state = targets
+                    .entry(key(&candidate.target.target.provider, &candidate.target.target.model))
+                    .or_insert_with(TargetState::new);
+                if !state.accepts() {
+                    return None;
+                }
+
+                let price = candidate.capabilities
+                    .as_ref()
+                    .and_then(|capabilities| capabilities.pricing.as_ref())
+                    .map(|pricing| {
+                        (pricing.input * prompt_tokens as f64 + pricing.output * completion_tokens as f64) / 1000.0
+                    })
+                    .unwrap_or(f64::INFINITY);
+                // Цели без замеров считаются быстрыми, чтобы по ним появилась статистика
+                let latency = state.percentile(0.5).unwrap_or(0) as f64;
+                let score = match policy {
+                    RoutingPolicy::CheapestThatFits => price,
+                    RoutingPolicy::Fastest => latency,
+                    RoutingPolicy::Weighted => {
+                        let weight = candidate.target.weight.max(0.0) * (1.0 - state.error_rate()).max(0.01);
+                        // Взвешенная случайная выборка: -ln(u) / w, меньшее значение выигрывает
+                        let uniform = (Uuid::new_v4().as_u128() as u64 & ((1 << 53) - 1)) as f64 / (1u64 << 53) as f64;
+                        -(1.0 - uniform).ln() / weight.max(f64::MIN_POSITIVE)
+                    },
+                };
+
+                Some((score, latency, state.error_rate(), candidate.target.target.clone()))
+            })
+            .collect::<Vec<_>>();
+
+        if ranked.is_empty() {
+            return Err(anyhow::anyhow!("Нет доступных целей маршрута"));
+        }
+
+        // Цели с частыми ошибками уходят в конец, при равенстве выигрывает более быстрая
+        ranked.sort_by(|a, b| (a.2 >= 0.5).cmp(&(b.2 >= 0.5))
+            .then(a.0.total_cmp(&b.0))
+            .then(a.1.total_cmp(&b.1))
+        );
+
+        let ranked = ranked.into_iter().map(|(_, _, _, target)| target).collect::<Vec<_>>();
+        if let Some(state) = targets.get_mut(&key(&ranked[0].provider, &ranked[0].model)) {
+            if state.circuit() == CircuitState::HalfOpen {
+                state.probing = Some(Instant::now());
+            }
+        }
+
+        Ok(ranked)
+    }
+}
+
+// Грубая оценка размера промпта: около четырёх символов на токен
+pub fn estimate_tokens(messages: &[ChatMessage]) -> u32 {
+    messages
+        .iter()
+        .map(|message| message.text_content().map(|text| text.chars().count()).unwrap_or_default() as u32 / 4 + 4)
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::StatusCode;
+
+    use super::*;
+    use crate::llm::catalog::Pricing;
+
+    fn target(provider: &str, model: &str) -> RouteTarget {
+        RouteTarget {
+            target: AliasTarget { provider: provider.into(), model: model.into() },
+            weight: 1.0,
+        }
+    }
+
+    fn capabilities(context_length: u32, price: f64) -> Option<ModelCapabilities> {
+        Some(ModelCapabilities {
+            context_length: Some(context_length),
+            pricing: Some(Pricing { input: price, output: price, currency: "RUB".into() }),
+            ..Default::default()
+        })
+    }
+
+    fn models(ranked: &[AliasTarget]) -> Vec<&str> {
+        ranked.iter().map(|target| target.model.as_str()).collect()
+    }
+
+    #[test]
+    fn cheapest_that_fits_skips_small_contexts() {
+        let router = Router::new();
+        let (small, cheap, expensive) = (target("a", "small"), target("a", "cheap"), target("b", "expensive"));
+
+        let ranked = router.rank(RoutingPolicy::CheapestThatFits, vec![
+            Candidate { target: &expensive, capabilities: capabilities(32_000, 2.0) },
+            Candidate { target: &small, capabilities: capabilities(1_000, 0.1) },
+            Candidate { target: &cheap, capabilities: capabilities(32_000, 1.0) },
+        ], 2_000, Some(500)).unwrap();
+
+        assert_eq!(models(&ranked), ["cheap", "expensive"]);
+    }
+
+    #[test]
+    fn huge_max_tokens_does_not_fit() {
+        let router = Router::new();
+        let model = target("a", "model");
+
+        let ranked = router.rank(RoutingPolicy::CheapestThatFits, vec![
+            Candidate { target: &model, capabilities: capabilities(32_000, 1.0) },
+        ], 2_000, Some(u32::MAX));
+
+        assert!(ranked.is_err());
+    }
+
+    #[test]
+    fn fastest_orders_by_median_latency() {
+        let router = Router::new();
+        let (slow, fast) = (target("a", "slow"), target("a", "fast"));
+        for _ in 0..3 {
+            router.record("a", "slow", Duration::from_millis(900), true);
+            router.record("a", "fast", Duration::from_millis(100), true);
+        }
+
+        let ranked = router.rank(RoutingPolicy::Fastest, vec![
+            Candidate { target: &slow, capabilities: None },
+            Candidate { target: &fast, capabilities: None },
+        ], 10, None).unwrap();
+
+        assert_eq!(models(&ranked), ["fast", "slow"]);
+    }
+
+    #[test]
+    fn failing_targets_go_last() {
+        let router = Router::new();
+        let (flaky, stable) = (target("a", "flaky"), target("a", "stable"));
+        router.record("a", "flaky", Duration::from_millis(10), true);
+        router.record("a", "flaky", Duration::from_millis(10), false);
+        router.record("a", "stable", Duration::from_millis(500), true);
+
+        let ranked = router.rank(RoutingPolicy::Fastest, vec![
+            Candidate { target: &flaky, capabilities: None },
+            Candidate { target: &stable, capabilities: None },
+        ], 10, None).unwrap();
+
+        assert_eq!(models(&ranked), ["stable", "flaky"]);
+    }
+
+    #[test]
+    fn circuit_opens_after_consecutive_failures() {
+        let router = Router::new();
+        let (broken, spare) = (target("a", "broken"), target("a", "spare"));
+        for _ in 0..CIRCUIT_FAILURE_THRESHOLD - 1 {
+            router.record("a", "broken", Duration::ZERO, false);
+        }
+        assert!(!router.is_open("a", "broken"));
+
+        router.record("a", "broken", Duration::ZERO, false);
+        assert!(router.is_open("A", "broken"));
+
+        let ranked = router.rank(RoutingPolicy::Fastest, vec![
+            Candidate { target: &broken, capabilities: None },
+            Candidate { target: &spare, capabilities: None },
+        ], 10, None).unwrap();
+        assert_eq!(models(&ranked), ["spare"]);
+
+        assert!(router.rank(RoutingPolicy::Fastest, vec![
+            Candidate { target: &broken, capabilities: None },
+        ], 10, None).is_err());
+    }
+
+    #[test]
+    fn half_open_circuit_lets_one_probe_through() {
+        let router = Router::new();
+        let broken = target("a", "broken");
+        for _ in 0..CIRCUIT_FAILURE_THRESHOLD {
+            router.record("a", "broken", Duration::ZERO, false);
+        }
+        // Срок размыкания истёк
+        router.targets.write().unwrap().get_mut("A/broken").unwrap().opened_at =
+            Some(Instant::now() - Duration::from_secs(CIRCUIT_COOLDOWN));
+
+        let candidates = || vec![Candidate { target: &broken, capabilities: None }];
+        assert!(router.rank(RoutingPolicy::Fastest, candidates(), 10, None).is_ok());
+        // Пока пробный запрос не завершён, другие не пропускаются
+        assert!(router.rank(RoutingPolicy::Fastest, candidates(), 10, None).is_err());
+
+        // Неудачная проба снова размыкает цепь, удачная замыкает
+        router.record("a", "broken", Duration::ZERO, false);
+        assert!(router.is_open("a", "broken"));
+        router.record("a", "broken", Duration::ZERO, true);
+        assert!(!router.is_open("a", "broken"));
+        assert!(router.rank(RoutingPolicy::Fastest, candidates(), 10, None).is_ok());
+    }
+
+    #[test]
+    fn falls_back_only_on_transient_errors() {
+        let upstream = |status| UpstreamError { status, body: String::new() };
+
+        assert!(should_fall_back(&upstream(StatusCode::SERVICE_UNAVAILABLE)));
+        assert!(should_fall_back(&upstream(StatusCode::TOO_MANY_REQUESTS)));
+        assert!(should_fall_back(&Unavailable("нет провайдера".into())));
+        assert!(!should_fall_back(&upstream(StatusCode::BAD_REQUEST)));
+        assert!(!should_fall_back(anyhow::anyhow!("некорректный запрос").as_ref()));
+    }
+
+    #[test]
+    fn send_conversion_keeps_error_types() {
+        let upstream: Box<dyn std::error::Error> = Box::new(UpstreamError {
+            status: StatusCode::BAD_GATEWAY,
+            body: String::new(),
+        });
+        let upstream = into_send(upstream);
+        assert!(upstream.is::<UpstreamError>());
+        assert!(should_fall_back(upstream.as_ref()));
+
+        let unavailable: Box<dyn std::error::Error> = Box::new(Unavailable("нет провайдера".into()));
+        assert!(into_send(unavailable).is::<Unavailable>());
+
+        let other: Box<dyn std::error::Error> = "некорректный запрос".into();
+        let other = into_send(other);
+        assert_eq!(other.to_string(), "некорректный запрос");
+        assert!(!should_fall_back(other.as_ref()));
+    }
+}
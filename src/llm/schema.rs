@@ -0,0 +1,287 @@
+use std::fmt;
+
+use serde_json::Value;
+
+use crate::llm::ResponseFormat;
+
+#[derive(Debug)]
+pub struct StructuredOutputError {
+    pub attempts: usize,
+    pub errors: Vec<String>,
+}
+
+impl fmt::Display for StructuredOutputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f, "Ответ модели не прошёл проверку после {} попыток: {}",
+            self.attempts, self.errors.join("; ")
+        )
+    }
+}
+
+impl std::error::Error for StructuredOutputError {}
+
+impl ResponseFormat {
+    pub fn instructions(&self) -> Option<String> {
+        match self {
+            ResponseFormat::Text => None,
+            ResponseFormat::JsonObject => Some(
+                "Ответь только корректным JSON-объектом, без пояснений и markdown-разметки.".into()
+            ),
+            ResponseFormat::JsonSchema { json_schema } => Some(format!(
+                "Ответь только корректным JSON, без пояснений и markdown-разметки. \
+                Ответ должен соответствовать JSON Schema:\n{}",
+                json_schema.schema
+            )),
+        }
+    }
+
+    pub fn validate(&self, content: &str) -> Result<Value, Vec<String>> {
+        let value = serde_json::from_str::<Value>(strip_code_fence(content))
+            .map_err(|e| vec![format!("Ответ не является корректным JSON: {}", e)])?;
+
+        let errors = match self {
+            ResponseFormat::Text => Vec::new(),
+            ResponseFormat::JsonObject if !value.is_object() => vec!["Ожидался JSON-объект".to_string()],
+            ResponseFormat::JsonObject => Vec::new(),
+            ResponseFormat::JsonSchema { json_schema } => {
+                let mut errors = Vec::new();
+                check(&json_schema.schema, &value, "$", &mut errors);
+                errors
+            },
+        };
+
+        if errors.is_empty() { Ok(value) } else { Err(errors) }
+    }
+}
+
+fn strip_code_fence(content: &str) -> &str {
+    let content = content.trim();
+    match content.strip_prefix("```") {
+        Some(fenced) => fenced
+            .split_once('\n')
+            .map(|(_, body)| body)
+            .unwrap_or(fenced)
+            .trim_end()
+            .trim_end_matches("```")
+            .trim(),
+        None => content,
+    }
+}
+
+fn type_matches(expected: &str, value: &Value) -> bool {
+    match expected {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64()
+            || value.as_f64().is_some_and(|number| number.fract() == 0.0),
+        "array" => value.is_array(),
+        "object" => value.is_object(),
+        _ => true,
+    }
+}
+
+// Поддерживается подмножество JSON Schema без $ref и pattern:
+// type, enum, const, ограничения чисел, строк, массивов и объектов, allOf/anyOf/oneOf/not
+fn check(schema: &Value, value: &Value, path: &str, errors: &mut Vec<String>) {
+    let schema = match schema {
+        Value::Bool(true) => return,
+        Value::Bool(false) => return errors.push(format!("{path}: значение запрещено схемой")),
+        Value::Object(schema) => schema,
+        _ => return,
+    };
+
+    if let Some(expected) = schema.get("type") {
+        let types = match expected {
+            Value::String(expected) => vec![expected.as_str()],
+            Value::Array(expected) => expected.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        if !types.is_empty() && !types.iter().any(|expected| type_matches(expected, value)) {
+            return errors.push(format!("{path}: ожидался тип {}", types.join(" | ")));
+        }
+    }
+
+    if let Some(Value::Array(variants)) = schema.get("enum") {
+        if !variants.contains(value) {
+            errors.push(format!("{path}: значение должно быть одним из {}", Value::Array(variants.clone())));
+        }
+    }
+    if let Some(expected) = schema.get("const") {
+        if expected != value {
+            errors.push(format!("{path}: значение должно быть равно {}", expected));
+        }
+    }
+
+    let bound = |key: &str| schema.get(key).and_then(Value::as_f64);
+    let count = |key: &str| schema.get(key).and_then(Value::as_u64).map(|count| count as usize);
+
+    match value {
+        Value::Number(number) => {
+            let number = number.as_f64().unwrap_or_default();
+            if bound("minimum").is_some_and(|minimum| number < minimum) {
+                errors.push(format!("{path}: значение меньше minimum"));
+            }
+            if bound("maximum").is_some_and(|maximum| number > maximum) {
+                errors.push(format!("{path}: значение больше maximum"));
+            }
+            if bound("exclusiveMinimum").is_some_and(|minimum| number <= minimum) {
+                errors.push(format!("{path}: значение должно быть больше exclusiveMinimum"));
+            }
+            if bound("exclusiveMaximum").is_some_and(|maximum| number >= maximum) {
+                errors.push(format!("{path}: значение должно быть меньше exclusiveMaximum"));
+            }
+        },
+        Value::String(string) => {
+            let length = string.chars().count();
+            if count("minLength").is_some_and(|minimum| length < minimum) {
+                errors.push(format!("{path}: строка короче minLength"));
+            }
+            if count("maxLength").is_some_and(|maximum| length > maximum) {
+                errors.push(format!("{path}: строка длиннее maxLength"));
+            }
+        },
+        Value::Array(items) => {
+            if count("minItems").is_some_and(|minimum| items.len() < minimum) {
+                errors.push(format!("{path}: элементов меньше minItems"));
+            }
+            if count("maxItems").is_some_and(|maximum| items.len() > maximum) {
+                errors.push(format!("{path}: элементов больше maxItems"));
+            }
+            if let Some(item_schema) = schema.get("items") {
+                for (index, item) in items.iter().enumerate() {
+                    check(item_schema, item, &format!("{path}[{index}]"), errors);
+                }
+            }
+        },
+        Value::Object(object) => {
+            if let Some(Value::Array(required)) = schema.get("required") {
+                for key in required.iter().filter_map(Value::as_str) {
+                    if !object.contains_key(key) {
+                        errors.push(format!("{path}: отсутствует обязательное поле {key}"));
+                    }
+                }
+            }
+
+            let properties = schema.get("properties").and_then(Value::as_object);
+            for (key, item) in object {
+                let item_path = format!("{path}.{key}");
+                match (properties.and_then(|properties| properties.get(key)), schema.get("additionalProperties")) {
+                    (Some(property), _) => check(property, item, &item_path, errors),
+                    (None, Some(additional)) => check(additional, item, &item_path, errors),
+                    (None, None) => {},
+                }
+            }
+        },
+        _ => {},
+    }
+
+    let passes = |schema: &Value| {
+        let mut nested = Vec::new();
+        check(schema, value, path, &mut nested);
+        nested.is_empty()
+    };
+
+    if let Some(Value::Array(schemas)) = schema.get("allOf") {
+        for schema in schemas {
+            check(schema, value, path, errors);
+        }
+    }
+    if let Some(Value::Array(schemas)) = schema.get("anyOf") {
+        if !schemas.iter().any(passes) {
+            errors.push(format!("{path}: значение не подходит ни под один вариант anyOf"));
+        }
+    }
+    if let Some(Value::Array(schemas)) = schema.get("oneOf") {
+        if schemas.iter().filter(|schema| passes(schema)).count() != 1 {
+            errors.push(format!("{path}: значение должно подходить ровно под один вариант oneOf"));
+        }
+    }
+    if let Some(schema) = schema.get("not") {
+        if passes(schema) {
+            errors.push(format!("{path}: значение не должно подходить под схему not"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::llm::JsonSchemaFormat;
+
+    fn schema(schema: Value) -> ResponseFormat {
+        ResponseFormat::JsonSchema {
+            json_schema: JsonSchemaFormat { name: "test".into(), description: None, schema, strict: None },
+        }
+    }
+
+    #[test]
+    fn accepts_fenced_json() {
+        let value = ResponseFormat::JsonObject.validate("```json\n{\"a\": 1}\n```").unwrap();
+        assert_eq!(value, json!({"a": 1}));
+    }
+
+    #[test]
+    fn rejects_invalid_json_and_non_objects() {
+        assert!(ResponseFormat::JsonObject.validate("не json").is_err());
+        assert!(ResponseFormat::JsonObject.validate("[1, 2]").is_err());
+    }
+
+    #[test]
+    fn checks_types_and_required_fields() {
+        let format = schema(json!({
+            "type": "object",
+            "required": ["name", "age"],
+            "properties": {
+                "name": {"type": "string", "minLength": 1},
+                "age": {"type": "integer", "minimum": 0},
+            },
+            "additionalProperties": false,
+        }));
+
+        assert!(format.validate(r#"{"name": "Иван", "age": 30}"#).is_ok());
+
+        let errors = format.validate(r#"{"name": "", "age": -1.5, "extra": true}"#).unwrap_err();
+        assert!(errors.iter().any(|error| error.starts_with("$.name: строка короче")));
+        assert!(errors.iter().any(|error| error.starts_with("$.age: ожидался тип integer")));
+        assert!(errors.iter().any(|error| error.starts_with("$.extra: значение запрещено")));
+
+        let errors = format.validate(r#"{"name": "Иван"}"#).unwrap_err();
+        assert_eq!(errors, vec!["$: отсутствует обязательное поле age".to_string()]);
+    }
+
+    #[test]
+    fn checks_arrays_and_enums() {
+        let format = schema(json!({
+            "type": "array",
+            "maxItems": 2,
+            "items": {"enum": ["a", "b"]},
+        }));
+
+        assert!(format.validate(r#"["a", "b"]"#).is_ok());
+        let errors = format.validate(r#"["a", "c", "b"]"#).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(errors.iter().any(|error| error.starts_with("$[1]: значение должно быть одним из")));
+    }
+
+    #[test]
+    fn checks_combinators() {
+        let format = schema(json!({
+            "oneOf": [{"type": "integer"}, {"type": "number", "minimum": 10}],
+        }));
+
+        assert!(format.validate("5").is_ok());
+        assert!(format.validate("10.5").is_ok());
+        // 12 подходит под оба варианта
+        assert!(format.validate("12").is_err());
+
+        let format = schema(json!({"anyOf": [{"type": "string"}, {"type": "null"}], "not": {"const": "x"}}));
+        assert!(format.validate("null").is_ok());
+        assert!(format.validate("\"x\"").is_err());
+        assert!(format.validate("1").is_err());
+    }
+}
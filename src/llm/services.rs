@@ -1,34 +1,80 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use std::str::FromStr;
 use std::{env, fs};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
 
 use async_trait::async_trait;
-use reqwest::{Client, Method, RequestBuilder};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bytes::Bytes;
+use futures::{StreamExt, TryStreamExt};
+use reqwest::multipart::{Form, Part};
+use reqwest::header::{HeaderValue, AUTHORIZATION};
+use reqwest::{Client, Method, RequestBuilder, StatusCode, Url};
 use ringbuffer::{AllocRingBuffer, RingBuffer};
 use secrecy::{ExposeSecret, Secret};
+use serde::Deserialize;
 use serde_json::json;
+use tokio::sync::mpsc::{self, UnboundedSender};
 use tokio::sync::RwLock;
 use tool_registry::ToolRegistry;
-use crate::config::ModelData;
-use crate::llm::provider::{ServiceChatRequest, ServiceChatResponse, ServiceEmbeddingRequest, ServiceEmbeddingResponse};
-use crate::llm::{ChatMessage, EmbeddedRequest, EmbeddedResponse, Tool, ToolChoice, HISTORY_SIZE};
-use crate::llm::{auth::TokenInterceptor, ChatRequest, ChatResponse, LLMService, RETRIES};
+use crate::config::{ModelData, NetworkSettings};
+use crate::shutdown;
+use crate::llm::attachments::{file_references, Attachment};
+use crate::llm::catalog::{ModelCapabilities, UpstreamModel};
+use crate::llm::content::{ContentPart, FileUpload, ImageUrl, MessageContent, UploadedFile};
+use crate::llm::provider::{ChatChoice, EmbeddingInput, ServiceChatRequest, ServiceChatResponse, ServiceEmbeddingRequest, ServiceEmbeddingResponse};
+use crate::llm::{ChatChunk, ChatEvent, ChatMessage, ChatStream, EmbeddedRequest, FunctionCall, ToolCall, EmbeddedResponse, EmbeddedUsage, Tool, ToolChoice, EMBEDDING_BATCH_SIZE, EMBEDDING_CONCURRENCY, GIGACHAT_EMBEDDING_BATCH_SIZE, HISTORY_SIZE};
+use crate::llm::{auth::{TokenInterceptor, TokenStatus}, http, requests::cancellable, ServiceHealth, ToolsStatus, schema::StructuredOutputError, ChatRequest, ChatResponse, LLMService, ResponseFormat, Usage, MAX_STRUCTURED_OUTPUT_RETRIES, RETRIES};
 
 use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
 use reqwest_retry::{RetryTransientMiddleware, policies::ExponentialBackoff};
 
+/// Провайдер ответил кодом ошибки.
+#[derive(Debug)]
+pub struct UpstreamError {
+    pub status: StatusCode,
+    pub body: String,
+}
+
+impl UpstreamError {
+    async fn from_response(response: reqwest::Response) -> Self {
+        let status = response.status();
+        Self { status, body: response.text().await.unwrap_or_default() }
+    }
+
+    // Перегрузка и сбои на стороне провайдера, а не ошибки самого запроса
+    pub fn retryable(&self) -> bool {
+        self.status.is_server_error() || self.status == StatusCode::TOO_MANY_REQUESTS
+    }
+}
+
+impl std::fmt::Display for UpstreamError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Провайдер ответил {}: {}", self.status, self.body)
+    }
+}
+
+impl std::error::Error for UpstreamError {}
+
 #[derive(Clone)]
 pub struct GenericLLMService<A> {
     auth: A,
     client: ClientWithMiddleware,
+    // Без retry: для запросов, тело которых нельзя повторить
+    http: Client,
+    // Изображения по ссылкам из запросов
+    images: Client,
     tools_registry: Arc<RwLock<ToolRegistry>>,
-    base_url: String
+    tools_status: Arc<std::sync::RwLock<ToolsStatus>>,
+    base_url: String,
+    embedding_batch_size: usize
 }
 
 impl<A> GenericLLMService<A> {
-    pub async fn new(auth: A, base_url: &str) -> anyhow::Result<Self> {
+    pub async fn new(auth: A, base_url: &str, embedding_batch_size: usize, network: &NetworkSettings) -> anyhow::Result<Self> {
         let retry_policy = ExponentialBackoff::builder()
             .build_with_max_retries(RETRIES);
   
@@ -38,15 +84,20 @@ impl<A> GenericLLMService<A> {
         //     .timeout(Duration::from_secs(TIMEOUT))
         //     .build()?;
 
-        let client = ClientBuilder::new(Client::new())
+        let http = http::client_builder(network)?.build()?;
+        let client = ClientBuilder::new(http.clone())
             .with(RetryTransientMiddleware::new_with_policy(retry_policy))
             .build();
         
         let instance = Self {
             auth,
             client,
+            http,
+            images: http::image_client(network)?,
             tools_registry: Arc::new(RwLock::new(ToolRegistry::new())),
-            base_url: base_url.to_string()
+            tools_status: Arc::new(std::sync::RwLock::new(ToolsStatus::default())),
+            base_url: base_url.to_string(),
+            embedding_batch_size: embedding_batch_size.max(1)
         };
 
         instance.start_tool_watcher().await;
@@ -55,6 +106,7 @@ impl<A> GenericLLMService<A> {
 
     pub async fn start_tool_watcher(&self) {
         let tool_registry = self.tools_registry.clone();
+        let tools_status = self.tools_status.clone();
         let tools_dir_name = match env::var("TOOLS_PATH") {
             Ok(path) => path,
             Err(_) => {
@@ -69,15 +121,20 @@ impl<A> GenericLLMService<A> {
             Err(e) => println!("Директория {tools_dir_name} не может быть создана: {}", e)
         };
     
-        if let Err(e) = tool_registry.write().await.load_from_dir(&tools_dir) {
+        if let Err(e) = load_tools(&tool_registry, &tools_status, &tools_dir).await {
             eprintln!("Failed to load tools: {}", e);
         }
     
-        tokio::spawn(async move {
-            loop {
-                tokio::time::sleep(Duration::from_secs(30)).await;
-                
-                if let Err(e) = tool_registry.write().await.load_from_dir(&tools_dir) {
+        // Задача держит слабые ссылки и завершается, когда сервис заменён или удалён
+        // и последний запрос к нему отпустил реестр инструментов
+        let tool_registry = Arc::downgrade(&tool_registry);
+        let tools_status = Arc::downgrade(&tools_status);
+        shutdown::spawn(async move {
+            while !shutdown::sleep(Duration::from_secs(30)).await {
+                let (Some(tool_registry), Some(tools_status)) = (tool_registry.upgrade(), tools_status.upgrade()) else {
+                    break;
+                };
+                if let Err(e) = load_tools(&tool_registry, &tools_status, &tools_dir).await {
                     eprintln!("Failed to reload tools: {}", e);
                 }
             }
@@ -85,150 +142,840 @@ impl<A> GenericLLMService<A> {
     }
 }
 
+// Результат загрузки запоминается для /status и /readyz
+async fn load_tools(
+    registry: &RwLock<ToolRegistry>,
+    status: &std::sync::RwLock<ToolsStatus>,
+    dir: &Path,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let result = registry.write().await.load_from_dir(dir);
+    let (count, specs) = {
+        let registry = registry.read().await;
+        (registry.tools_specs().len(), serde_json::to_string(&registry.tools_specs()).unwrap_or_default())
+    };
+    let mut hasher = DefaultHasher::new();
+    specs.hash(&mut hasher);
+
+    let mut status = status.write().unwrap();
+    status.count = count;
+    status.fingerprint = hasher.finish();
+    match &result {
+        Ok(_) => {
+            status.loaded = true;
+            status.reloaded_at = Some(chrono::Utc::now());
+            status.error = None;
+        },
+        Err(e) => status.error = Some(e.to_string()),
+    }
+
+    result
+}
+
 #[async_trait]
-impl<A: AuthProvider + Sync + Send + Clone +'static> LLMService for GenericLLMService<A> {
-    async fn chat(&self, request: ServiceChatRequest) -> Result<ServiceChatResponse, Box<dyn std::error::Error>> {
-        let mut history = AllocRingBuffer::with_capacity(HISTORY_SIZE);
-        let mut response = self._send(
-            request.messages, &mut history,
-            request.model.clone(), request.temperature
-        ).await?;
+impl<A: AuthProvider + Dialect + Sync + Send + Clone +'static> LLMService for GenericLLMService<A> {
+    async fn chat(&self, mut request: ServiceChatRequest) -> Result<ServiceChatResponse, Box<dyn std::error::Error>> {
+        request.messages = cancellable(&request.cancel, self._translate_all(request.messages)).await??;
+        let format = request.response_format
+            .clone()
+            .filter(|format| !matches!(format, ResponseFormat::Text));
+        let Some(format) = format else {
+            let (choices, usage, _) = self._complete(request.messages.clone(), &request).await?;
+            return Ok(ServiceChatResponse::from_choices(choices, usage));
+        };
 
-        println!("HERE {:?}", response);
+        // DeepSeek требует упоминания JSON в промпте даже для нативного json_object,
+        // поэтому инструкции не добавляются только для нативной json_schema
+        let mut messages = request.messages.clone();
+        let native_schema = matches!(format, ResponseFormat::JsonSchema { .. })
+            && self.auth.supports_response_format(&format);
+        if let Some(instructions) = format.instructions().filter(|_| !native_schema) {
+            add_system_instructions(&mut messages, instructions);
+        }
 
-        if let Some(message) = &response.choices[0].message {
-            if let Some(tool_calls) = &message.tool_calls {
-                let mut tool_buffer = Vec::new();
-                for tool_call in tool_calls {
-                    let tool_registry = self.tools_registry.read().await;
-                    let name = &tool_call.function.name;
-
-                    if let Some(tool) = tool_registry.get_tool(name) {
-                        let arguments = serde_json::Value::from_str(
-                            &tool_call.function.arguments.clone()
-                        )?;
-                        let tool_responce = match tool.execute(arguments).await {
-                            Ok(result)=> result,
-                            Err(e) => {
-                                json!({
-                                    "error": e.to_string()
-                                })
-                            }
-                        };
-
-                        println!("HERE {:?}", tool_responce);
-
-                        tool_buffer.push(ChatMessage {
-                            role: "tool".into(),
-                            content: Some(serde_json::to_string(&tool_responce)?),
-                            tool_calls: None,
-                            tool_call_id: Some(tool_call.id.clone()),
-                            name: Some(name.clone()),
-                        });
-                    } else {
-                        println!(
-                            "Инструмент не найден: {}",
-                            tool_call.function.name
-                        );
-                    }
+        // Значение по умолчанию из настроек подставляет LlmProvider
+        let retries = request.response_retries
+            .unwrap_or_default()
+            .min(MAX_STRUCTURED_OUTPUT_RETRIES);
+        let mut usage = Usage::default();
+        let mut errors = Vec::new();
+        // Повторы получают результаты инструментов первой попытки и сами инструменты не вызывают
+        let mut retry = request.clone();
+        retry.skip_tools = true;
+        for attempt in 0..=retries {
+            let (choices, round, tool_round) = self._complete(
+                messages.clone(), if attempt == 0 { &request } else { &retry }
+            ).await?;
+            usage.add(&round);
+            messages.extend(tool_round);
+
+            // При n > 1 подходит первый вариант, прошедший проверку
+            let mut rejected = None;
+            let mut accepted = None;
+            for (index, choice) in choices.iter().enumerate() {
+                let content = choice.content.clone().unwrap_or_default();
+                match format.validate(&content) {
+                    Ok(value) => {
+                        accepted = Some((index, value.to_string()));
+                        break;
+                    },
+                    Err(found) => if rejected.is_none() {
+                        rejected = Some((content, found));
+                    },
                 }
- 
-                response = self._send(
-                    tool_buffer,
-                    &mut history,
-                    request.model.clone(),
-                    request.temperature
-                ).await?;
             }
+            if let Some((index, content)) = accepted {
+                let mut response = ServiceChatResponse::from_choices(choices, usage);
+                let choice = &mut response.choices[index];
+                choice.content = Some(content.clone());
+                response.attachments = choice.attachments.clone();
+                response.content = Some(content);
+                return Ok(response);
+            }
+
+            let (content, found) = rejected.unwrap_or_default();
+            errors = found;
+            messages.push(ChatMessage::text("assistant", content));
+            messages.push(ChatMessage::text("user", format!(
+                "Ответ не прошёл проверку:\n- {}\nИсправь ответ и верни только JSON.",
+                errors.join("\n- ")
+            )));
         }
-        history.clear();
 
-        let message = response.choices
-            .first()
-            .and_then(|choice| choice.message.as_ref())
-            .ok_or_else(|| anyhow::anyhow!("No valid message in response"))?;
+        Err(StructuredOutputError { attempts: retries + 1, errors }.into())
+    }
+
+    async fn chat_stream(&self, request: ServiceChatRequest) -> Result<ChatStream, Box<dyn std::error::Error>> {
+        // Отправленные клиенту фрагменты уже не проверить и не переспросить
+        if request.response_format.as_ref().is_some_and(|format| !matches!(format, ResponseFormat::Text)) {
+            return Err(anyhow::anyhow!("response_format не поддерживается в потоковом режиме").into());
+        }
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        let service = self.clone();
 
-        Ok(ServiceChatResponse { content: message.content.clone() })
+        // Если получатель закрыт, например клиент отменил генерацию или отключился,
+        // запрос к провайдеру и инструменты останавливаются
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = tx.closed() => {},
+                result = service._stream_chat(request, &tx) => if let Err(e) = result {
+                    let _ = tx.send(Err(e));
+                },
+            }
+        });
+
+        Ok(futures::stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|event| (event, rx))
+        }).boxed())
+    }
+
+    fn supports_param(&self, name: &str) -> bool {
+        self.auth.supports_param(name)
+    }
+
+    async fn upload(&self, file: FileUpload) -> Result<UploadedFile, Box<dyn std::error::Error>> {
+        self._check_files_api()?;
+        Ok(self._upload(file).await?)
+    }
+
+    async fn files(&self) -> Result<Vec<UploadedFile>, Box<dyn std::error::Error>> {
+        self._check_files_api()?;
+        let response = self._files_request(Method::GET, &[]).await?;
+        Ok(serde_json::from_str::<FileList>(&response.text().await?)?.data)
+    }
+
+    async fn file(&self, id: &str) -> Result<UploadedFile, Box<dyn std::error::Error>> {
+        self._check_files_api()?;
+        let response = self._files_request(Method::GET, &[id]).await?;
+        Ok(serde_json::from_str::<UploadedFile>(&response.text().await?)?)
+    }
+
+    async fn file_content(&self, id: &str) -> Result<Attachment, Box<dyn std::error::Error>> {
+        self._check_files_api()?;
+        Ok(self._file_content(id).await?)
+    }
+
+    async fn delete_file(&self, id: &str) -> Result<bool, Box<dyn std::error::Error>> {
+        self._check_files_api()?;
+        let response = self._files_request(Method::POST, &[id, "delete"]).await?;
+        Ok(serde_json::from_str::<DeletedFile>(&response.text().await?)?.deleted)
+    }
+
+    async fn models(&self) -> Result<Vec<UpstreamModel>, Box<dyn std::error::Error>> {
+        let response = self._execute(|| Ok(self.auth.with_auth(
+            self.http
+                .request(Method::GET, format!("{}/models", self.base_url))
+                .header("Accept", "application/json")
+        ).build()?)).await?;
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!(
+                "Models request failed: {} {}",
+                response.status(), response.text().await?
+            ).into());
+        }
+
+        Ok(serde_json::from_str::<ModelList>(&response.text().await?)?.data)
+    }
+
+    fn model_capabilities(&self, model: &UpstreamModel) -> ModelCapabilities {
+        self.auth.model_capabilities(model)
+    }
+
+    fn health(&self) -> ServiceHealth {
+        ServiceHealth {
+            token: self.auth.token_status(),
+            tools: self.tools_status.read().unwrap().clone(),
+        }
     }
 
     async fn embedded(&self, request: ServiceEmbeddingRequest) -> Result<ServiceEmbeddingResponse, Box<dyn std::error::Error>> {
-        let body = EmbeddedRequest {
-            model: request.model,
-            input: vec![request.input],
-        };
+        let single = matches!(request.input, EmbeddingInput::Single(_));
+        let inputs = request.input.into_vec();
+
+        let mut batches = futures::stream::iter(
+            inputs.chunks(self.embedding_batch_size).map(<[String]>::to_vec).enumerate()
+        )
+            .map(|(index, input)| {
+                let model = request.model.clone();
+                async move { self._embed(model, input).await.map(|response| (index, response)) }
+            })
+            .buffer_unordered(EMBEDDING_CONCURRENCY)
+            .try_collect::<Vec<_>>()
+            .await?;
+        batches.sort_by_key(|(index, _)| *index);
+
+        let mut embeddings = Vec::with_capacity(inputs.len());
+        let mut usage = EmbeddedUsage::default();
+        for (_, mut response) in batches {
+            response.data.sort_by_key(|data| data.index);
+            usage.prompt_tokens += match response.usage {
+                Some(usage) => usage.prompt_tokens,
+                None => response.data.iter()
+                    .filter_map(|data| data.usage.as_ref())
+                    .map(|usage| usage.prompt_tokens)
+                    .sum(),
+            };
+            embeddings.extend(response.data.into_iter().map(|data| data.embedding));
+        }
+
+        if embeddings.len() != inputs.len() {
+            return Err(anyhow::anyhow!(
+                "Expected {} embeddings, got {}", inputs.len(), embeddings.len()
+            ).into());
+        }
 
-        let body = serde_json::to_vec(&body)?;
+        Ok(ServiceEmbeddingResponse {
+            content: if single { embeddings[0].clone() } else { Vec::new() },
+            embeddings,
+            usage,
+        })
+    }
+}
+
+impl<A: AuthProvider + Dialect + Sync + Send + Clone +'static>  GenericLLMService<A> {
+    async fn _complete(
+        &self,
+        messages: Vec<ChatMessage>,
+        request: &ServiceChatRequest
+    ) -> Result<(Vec<ChatChoice>, Usage, Vec<ChatMessage>), Box<dyn std::error::Error>> {
+        let cancel = &request.cancel;
+        let mut history = AllocRingBuffer::with_capacity(HISTORY_SIZE);
+        let mut response = cancellable(cancel, self._send(
+            messages, &mut history, request
+        )).await??;
+        let mut usage = response.usage.clone().unwrap_or_default();
+
+        println!("HERE {:?}", response);
+
+        // Инструменты выполняются один раз, следующий раунд снова вернёт n вариантов
+        // Вызов инструментов и их результаты, чтобы повтор мог продолжить без нового вызова
+        let mut tool_round = Vec::new();
+        let tool_message = response.choices
+            .iter()
+            .filter_map(|choice| choice.message.as_ref())
+            .find(|message| message.tool_calls.is_some())
+            .filter(|_| !request.skip_tools)
+            .cloned();
+        if let Some(tool_message) = tool_message {
+            let tool_calls = tool_message.tool_calls.as_deref().unwrap_or_default();
+            let tool_buffer = cancellable(cancel, self._execute_tools(tool_calls, &|_| ())).await??;
+            tool_round.push(tool_message);
+            tool_round.extend(tool_buffer.iter().cloned());
+
+            response = cancellable(cancel, self._send(
+                tool_buffer,
+                &mut history,
+                request
+            )).await??;
+            if let Some(round) = &response.usage {
+                usage.add(round);
+            }
+        }
+        history.clear();
+
+        let mut choices = response.choices
+            .into_iter()
+            .filter_map(|choice| {
+                let score = choice.mean_logprob();
+                Some(ChatChoice {
+                    index: 0,
+                    content: choice.message?.text_content(),
+                    finish_reason: Some(choice.finish_reason),
+                    score,
+                    ..Default::default()
+                })
+            })
+            .enumerate()
+            .map(|(index, choice)| ChatChoice { index: index as u32, ..choice })
+            .collect::<Vec<_>>();
+        if choices.is_empty() {
+            return Err(anyhow::anyhow!("No valid message in response").into());
+        }
+
+        if self.auth.returns_file_references() {
+            for choice in &mut choices {
+                for file_id in file_references(choice.content.as_deref().unwrap_or_default()) {
+                    choice.attachments.push(cancellable(cancel, self._file_content(&file_id)).await??);
+                }
+            }
+        }
+
+        Ok((choices, usage, tool_round))
+    }
+
+    async fn _embed(&self, model: String, input: Vec<String>) -> anyhow::Result<EmbeddedResponse> {
+        let body = Bytes::from(serde_json::to_vec(&EmbeddedRequest { model, input })?);
 
-        let request = self.auth.with_auth(
-            Client::new()
+        let response = self._execute(|| Ok(self.auth.with_auth(
+            self.http
                 .request(Method::POST, format!("{}/embeddings", self.base_url))
                 .header("Accept", "application/json")
                 .header("Content-Type", "application/json")
-                .body(body)
-        ).build()?;
-
-        let response = self.client.execute(request).await?;
+                .body(body.clone())
+        ).build()?)).await?;
         let response = response.text().await?;
-        let response = serde_json::from_str::<EmbeddedResponse>(&response)?;
 
-        Ok(ServiceEmbeddingResponse { content: response.data[0].embedding.clone() })
+        Ok(serde_json::from_str::<EmbeddedResponse>(&response)?)
     }
-}
 
-impl<A: AuthProvider + Sync + Send + Clone +'static>  GenericLLMService<A> {
-    async fn _send(
+    async fn _execute_tools(
+        &self,
+        tool_calls: &[ToolCall],
+        on_event: &(dyn Fn(ChatEvent) + Send + Sync),
+    ) -> anyhow::Result<Vec<ChatMessage>> {
+        let mut tool_buffer = Vec::new();
+        for tool_call in tool_calls {
+            let tool_registry = self.tools_registry.read().await;
+            let name = &tool_call.function.name;
+
+            if let Some(tool) = tool_registry.get_tool(name) {
+                on_event(ChatEvent::ToolStarted { id: tool_call.id.clone(), name: name.clone() });
+
+                let arguments = serde_json::Value::from_str(
+                    &tool_call.function.arguments.clone()
+                )?;
+                let (tool_responce, error) = match tool.execute(arguments).await {
+                    Ok(result)=> (result, None),
+                    Err(e) => {
+                        (json!({
+                            "error": e.to_string()
+                        }), Some(e.to_string()))
+                    }
+                };
+
+                println!("HERE {:?}", tool_responce);
+                on_event(ChatEvent::ToolFinished { id: tool_call.id.clone(), name: name.clone(), error });
+
+                tool_buffer.push(ChatMessage {
+                    role: "tool".into(),
+                    content: Some(serde_json::to_string(&tool_responce)?.into()),
+                    tool_calls: None,
+                    tool_call_id: Some(tool_call.id.clone()),
+                    name: Some(name.clone()),
+                    attachments: None,
+                });
+            } else {
+                println!(
+                    "Инструмент не найден: {}",
+                    tool_call.function.name
+                );
+            }
+        }
+
+        Ok(tool_buffer)
+    }
+
+    async fn _request(
         &self,
         messages: Vec<ChatMessage>,
         history: &mut AllocRingBuffer<ChatMessage>,
-        model: String,
-        temperature: f32
-    ) -> Result<ChatResponse, Box<dyn std::error::Error>> {
+        request: &ServiceChatRequest,
+        stream: bool
+    ) -> anyhow::Result<Bytes> {
         let tools = self.tools_registry.read().await;
         let tools = match serde_json::from_value::<Vec<Tool>>(
             serde_json::json!(tools.tools_specs())
         )? {
-            vec if !vec.is_empty() => Some(vec),
+            vec if !vec.is_empty() && !request.skip_tools => Some(vec),
             _ => None,
         };
 
-        history.extend(messages);
+        for message in messages {
+            history.enqueue(message);
+        }
 
         let body = ChatRequest {
-            model: model,
+            model: request.model.clone(),
             messages: history.to_vec(),
-            temperature: Some(temperature),
+            temperature: request.temperature,
             tools: tools,
-            tool_choice: Some(ToolChoice::Auto),
+            tool_choice: (!request.skip_tools).then_some(ToolChoice::Auto),
+            stream: stream.then_some(true),
+            response_format: request.response_format
+                .clone()
+                .filter(|format| self.auth.supports_response_format(format)),
+            sampling: request.sampling.clone(),
         };
 
-        let body = serde_json::to_vec(&body)?;
+        Ok(serde_json::to_vec(&body)?.into())
+    }
 
-        
-        let request = self.auth.with_auth(
-        Client::new()
+    // Тело сериализуется один раз, при повторе копируется только ссылка на него
+    fn _chat_request(&self, body: &Bytes) -> anyhow::Result<reqwest::Request> {
+        Ok(self.auth.with_auth(
+        self.http
             .request(Method::POST, format!("{}/chat/completions", self.base_url))
             .header("Accept", "application/json")
             .header("Content-Type", "application/json")
-            .body(body)
-        ).build()?;
-        
-        let response = self.client.execute(request).await?;
+            .body(body.clone())
+        ).build()?)
+    }
+
+    // Вложения переводятся в формат провайдера один раз на запрос, до повторов и раундов инструментов
+    async fn _translate_all(&self, messages: Vec<ChatMessage>) -> anyhow::Result<Vec<ChatMessage>> {
+        let mut translated = Vec::with_capacity(messages.len());
+        for message in messages {
+            translated.push(self._translate(message).await?);
+        }
+        Ok(translated)
+    }
+
+    async fn _translate(&self, mut message: ChatMessage) -> anyhow::Result<ChatMessage> {
+        let Some(MessageContent::Parts(parts)) = message.content.take() else {
+            return Ok(message);
+        };
+
+        if !self.auth.uploads_attachments() {
+            // OpenAI-совместимый формат: изображения передаются как image_url
+            let parts = parts
+                .into_iter()
+                .map(|part| match part {
+                    ContentPart::ImageBase64 { media_type, data } => Ok(ContentPart::ImageUrl {
+                        image_url: ImageUrl { url: format!("data:{media_type};base64,{data}"), detail: None },
+                    }),
+                    ContentPart::File { file_id } => Err(anyhow::anyhow!(
+                        "Провайдер не поддерживает вложение файлов: {}", file_id
+                    )),
+                    part => Ok(part),
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            message.content = Some(MessageContent::Parts(parts));
+            return Ok(message);
+        }
+
+        // GigaChat принимает только текст и идентификаторы заранее загруженных файлов
+        let mut text = Vec::new();
+        let mut attachments = message.attachments.take().unwrap_or_default();
+        for (index, part) in parts.into_iter().enumerate() {
+            let file = match part {
+                ContentPart::Text { text: part } => {
+                    text.push(part);
+                    continue;
+                },
+                ContentPart::File { file_id } => {
+                    attachments.push(file_id);
+                    continue;
+                },
+                ContentPart::ImageUrl { image_url } => {
+                    let (content_type, bytes) = http::download(&self.images, &image_url.url).await?;
+                    FileUpload {
+                        filename: image_filename(index, &content_type),
+                        content_type,
+                        bytes,
+                    }
+                },
+                ContentPart::ImageBase64 { media_type, data } => FileUpload {
+                    filename: image_filename(index, &media_type),
+                    bytes: STANDARD.decode(data)?,
+                    content_type: media_type,
+                },
+            };
+            attachments.push(self._upload(file).await?.id);
+        }
+
+        message.content = Some(MessageContent::Text(text.join("\n")));
+        message.attachments = (!attachments.is_empty()).then_some(attachments);
+        Ok(message)
+    }
+
+    async fn _upload(&self, file: FileUpload) -> anyhow::Result<UploadedFile> {
+        let bytes = Bytes::from(file.bytes);
+        let form = || -> anyhow::Result<Form> {
+            let part = Part::stream_with_length(bytes.clone(), bytes.len() as u64)
+                .file_name(file.filename.clone())
+                .mime_str(&file.content_type)?;
+            Ok(Form::new()
+                .text("purpose", "general")
+                .part("file", part))
+        };
+
+        let response = self._execute(|| Ok(self.auth.with_auth(
+            self.http
+                .request(Method::POST, format!("{}/files", self.base_url))
+                .header("Accept", "application/json")
+                .multipart(form()?)
+        ).build()?)).await?;
+
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!(
+                "Upload failed: {} {}",
+                response.status(), response.text().await?
+            ));
+        }
+
+        Ok(serde_json::from_str::<UploadedFile>(&response.text().await?)?)
+    }
+
+    // При ответе 401 токен обновляется и запрос повторяется один раз. Тело multipart
+    // нельзя клонировать для повторов, такой запрос идёт без retry-клиента и не повторяется
+    // `build` собирает запрос с текущими учётными данными; после 401 и их обновления
+    // запрос собирается заново, а не копируется заранее на случай повтора
+    async fn _execute(&self, build: impl Fn() -> anyhow::Result<reqwest::Request>) -> anyhow::Result<reqwest::Response> {
+        let request = build()?;
+        let rejected = request.headers().get(AUTHORIZATION).cloned();
+        let response = self._send_request(request).await?;
+
+        if response.status() != StatusCode::UNAUTHORIZED || !self.auth.reauthorize(rejected.as_ref()).await {
+            return Ok(response);
+        }
+        self._send_request(build()?).await
+    }
+
+    // Потоковое тело, например multipart, нельзя повторить, поэтому оно уходит без промежуточного слоя повторов
+    async fn _send_request(&self, request: reqwest::Request) -> anyhow::Result<reqwest::Response> {
+        if request.body().is_none_or(|body| body.as_bytes().is_some()) {
+            Ok(self.client.execute(request).await?)
+        } else {
+            Ok(self.http.execute(request).await?)
+        }
+    }
+
+    fn _check_files_api(&self) -> anyhow::Result<()> {
+        if !self.auth.files_api() {
+            return Err(anyhow::anyhow!("Провайдер не поддерживает files API"));
+        }
+        Ok(())
+    }
+
+    // Идентификатор файла приходит от клиента или из ответа модели, поэтому
+    // он кодируется как один сегмент пути и не может сменить адрес запроса
+    fn _files_url(&self, segments: &[&str]) -> anyhow::Result<Url> {
+        if let Some(id) = segments.first().filter(|id| id.is_empty() || **id == "." || **id == "..") {
+            return Err(anyhow::anyhow!("Некорректный идентификатор файла - {}", id));
+        }
+
+        let mut url = Url::parse(&format!("{}/files", self.base_url))?;
+        url.path_segments_mut()
+            .map_err(|_| anyhow::anyhow!("Некорректный адрес провайдера - {}", self.base_url))?
+            .extend(segments);
+        Ok(url)
+    }
+
+    async fn _files_request(&self, method: Method, segments: &[&str]) -> anyhow::Result<reqwest::Response> {
+        let response = self._execute(|| Ok(self.auth.with_auth(
+            self.http
+                .request(method.clone(), self._files_url(segments)?)
+                .header("Accept", "application/json")
+        ).build()?)).await?;
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!(
+                "Files request failed: {} {}",
+                response.status(), response.text().await?
+            ));
+        }
+
+        Ok(response)
+    }
+
+    async fn _file_content(&self, id: &str) -> anyhow::Result<Attachment> {
+        let response = self._execute(|| Ok(self.auth.with_auth(
+            self.http
+                .request(Method::GET, self._files_url(&[id, "content"])?)
+                .header("Accept", "application/jpg")
+        ).build()?)).await?;
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!(
+                "File download failed: {} {}",
+                response.status(), response.text().await?
+            ));
+        }
+
+        let content_type = response.headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("image/jpeg")
+            .to_string();
+
+        Ok(Attachment {
+            file_id: id.to_string(),
+            content_type,
+            bytes: response.bytes().await?.to_vec(),
+            ..Default::default()
+        })
+    }
+
+    async fn _send(
+        &self,
+        messages: Vec<ChatMessage>,
+        history: &mut AllocRingBuffer<ChatMessage>,
+        request: &ServiceChatRequest
+    ) -> Result<ChatResponse, Box<dyn std::error::Error>> {
+        let body = self._request(messages, history, request, false).await?;
+
+        let response = self._execute(|| self._chat_request(&body)).await?;
+        if !response.status().is_success() {
+            return Err(UpstreamError::from_response(response).await.into());
+        }
         let response = response.text().await?;
 
         println!("HERE RESPONCE {:?}", response);
         
         let response = serde_json::from_str::<ChatResponse>(&response)?;
-        if let Some(message) = &response.choices[0].message {
+
+        // В историю попадает вариант с вызовом инструментов, иначе первый
+        let message = response.choices
+            .iter()
+            .filter_map(|choice| choice.message.as_ref())
+            .find(|message| message.tool_calls.is_some())
+            .or_else(|| response.choices.first().and_then(|choice| choice.message.as_ref()));
+        if let Some(message) = message {
             history.enqueue(message.clone());
         }
 
         Ok(response)
     }
-}
 
+    async fn _stream_chat(
+        &self,
+        request: ServiceChatRequest,
+        tx: &UnboundedSender<anyhow::Result<ChatEvent>>
+    ) -> anyhow::Result<()> {
+        let cancel = request.cancel.clone();
+        let mut history = AllocRingBuffer::new(HISTORY_SIZE);
+        let mut messages = cancellable(&cancel, self._translate_all(request.messages.clone())).await??;
+        let mut tools_executed = false;
+        let mut usage = None::<Usage>;
+
+        loop {
+            let (message, finish_reason, round) = cancellable(&cancel, self._send_stream(
+                messages, &mut history, &request, tx
+            )).await??;
+            if let Some(round) = round {
+                usage.get_or_insert_with(Usage::default).add(&round);
+            }
+
+            match message.tool_calls {
+                Some(tool_calls) if !tools_executed && !request.skip_tools => {
+                    messages = cancellable(&cancel, self._execute_tools(&tool_calls, &|event| {
+                        let _ = tx.send(Ok(event));
+                    })).await??;
+                    tools_executed = true;
+                },
+                _ => {
+                    let _ = tx.send(Ok(ChatEvent::Done { finish_reason, usage }));
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    async fn _send_stream(
+        &self,
+        messages: Vec<ChatMessage>,
+        history: &mut AllocRingBuffer<ChatMessage>,
+        request: &ServiceChatRequest,
+        tx: &UnboundedSender<anyhow::Result<ChatEvent>>
+    ) -> anyhow::Result<(ChatMessage, Option<String>, Option<Usage>)> {
+        let body = self._request(messages, history, request, true).await?;
+        let response = self._execute(|| self._chat_request(&body)).await?;
+
+        if !response.status().is_success() {
+            return Err(UpstreamError::from_response(response).await.into());
+        }
+
+        let mut body = response.bytes_stream();
+        let mut buffer = Vec::new();
+        let mut content = String::new();
+        let mut tool_calls = Vec::<ToolCall>::new();
+        let mut finish_reason = None;
+        let mut usage = None;
+
+        'read: while let Some(bytes) = body.next().await {
+            buffer.extend_from_slice(&bytes?);
+
+            while let Some(position) = buffer.iter().position(|byte| *byte == b'\n') {
+                let line = buffer.drain(..=position).collect::<Vec<_>>();
+                let line = String::from_utf8_lossy(&line);
+                let Some(data) = line.trim().strip_prefix("data:") else {
+                    continue;
+                };
+
+                let data = data.trim();
+                if data == "[DONE]" {
+                    break 'read;
+                }
+
+                let chunk = serde_json::from_str::<ChatChunk>(data)?;
+                if chunk.usage.is_some() {
+                    usage = chunk.usage;
+                }
+                for choice in chunk.choices.into_iter().filter(|choice| choice.index == 0) {
+                    if choice.finish_reason.is_some() {
+                        finish_reason = choice.finish_reason;
+                    }
+                    let Some(delta) = choice.delta else {
+                        continue;
+                    };
+
+                    if let Some(text) = delta.content.filter(|text| !text.is_empty()) {
+                        content.push_str(&text);
+                        tx.send(Ok(ChatEvent::Delta { content: text }))
+                            .map_err(|_| anyhow::anyhow!("Клиент отключился"))?;
+                    }
+
+                    for call in delta.tool_calls.unwrap_or_default() {
+                        while tool_calls.len() <= call.index {
+                            tool_calls.push(ToolCall {
+                                id: String::new(),
+                                type_: "function".into(),
+                                function: FunctionCall { name: String::new(), arguments: String::new() },
+                            });
+                        }
+
+                        let tool_call = &mut tool_calls[call.index];
+                        if let Some(id) = call.id {
+                            tool_call.id = id;
+                        }
+                        if let Some(type_) = call.type_ {
+                            tool_call.type_ = type_;
+                        }
+                        if let Some(function) = call.function {
+                            tool_call.function.name.push_str(&function.name.unwrap_or_default());
+                            tool_call.function.arguments.push_str(&function.arguments.unwrap_or_default());
+                        }
+                    }
+                }
+            }
+        }
 
+        let message = ChatMessage {
+            role: "assistant".into(),
+            content: (!content.is_empty()).then_some(content.into()),
+            tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+            tool_call_id: None,
+            name: None,
+            attachments: None,
+        };
+        history.enqueue(message.clone());
+
+        Ok((message, finish_reason, usage))
+    }
+}
+
+#[async_trait]
 pub trait AuthProvider {
     fn with_auth(&self, req: RequestBuilder) -> RequestBuilder;
+
+    // Вызывается, когда провайдер отклонил заголовок авторизации с 401;
+    // true, если учётные данные обновлены и запрос стоит повторить
+    async fn reauthorize(&self, _rejected: Option<&HeaderValue>) -> bool {
+        false
+    }
+
+    // Состояние временного токена, если провайдер его получает
+    fn token_status(&self) -> Option<TokenStatus> {
+        None
+    }
+}
+
+// Возможности API конкретного провайдера
+pub trait Dialect {
+    fn supports_response_format(&self, _format: &ResponseFormat) -> bool {
+        false
+    }
+
+    // Имена параметров из `SamplingParams`, которые понимает API провайдера
+    fn supports_param(&self, _name: &str) -> bool {
+        false
+    }
+
+    // Вложения загружаются в files API и передаются идентификаторами
+    fn uploads_attachments(&self) -> bool {
+        false
+    }
+
+    fn files_api(&self) -> bool {
+        false
+    }
+
+    // Ответ может ссылаться на сгенерированные файлы через `<img src="...">`
+    fn returns_file_references(&self) -> bool {
+        false
+    }
+
+    // Известные возможности модели, которые провайдер не сообщает в `/models`
+    fn model_capabilities(&self, _model: &UpstreamModel) -> ModelCapabilities {
+        ModelCapabilities::default()
+    }
+}
+
+#[derive(Deserialize)]
+struct ModelList {
+    data: Vec<UpstreamModel>,
+}
+
+#[derive(Deserialize)]
+struct FileList {
+    data: Vec<UploadedFile>,
+}
+
+#[derive(Deserialize)]
+struct DeletedFile {
+    deleted: bool,
+}
+
+// Инструкции дописываются в системное сообщение клиента, если оно есть
+fn add_system_instructions(messages: &mut Vec<ChatMessage>, instructions: String) {
+    match messages.first_mut() {
+        Some(message) if message.role == "system" => match &mut message.content {
+            Some(MessageContent::Text(text)) => {
+                text.push_str("\n\n");
+                text.push_str(&instructions);
+            },
+            Some(MessageContent::Parts(parts)) => parts.push(ContentPart::Text { text: instructions }),
+            None => message.content = Some(MessageContent::Text(instructions)),
+        },
+        _ => messages.insert(0, ChatMessage::text("system", instructions)),
+    }
+}
+
+fn image_filename(index: usize, content_type: &str) -> String {
+    let extension = content_type
+        .strip_prefix("image/")
+        .map(|subtype| subtype.split(';').next().unwrap_or(subtype).trim())
+        .filter(|subtype| !subtype.is_empty())
+        .unwrap_or("jpg");
+    format!("image-{index}.{extension}")
 }
 
 // Реализация для GigaChat
@@ -239,21 +986,72 @@ pub struct GigaChatAuth {
 }
 
 impl GigaChatAuth {
-    pub async fn new(api_key: Secret<String>, scope: Option<String>) -> anyhow::Result<Self> {
+    pub async fn new(api_key: Secret<String>, scope: Option<String>, network: &NetworkSettings) -> anyhow::Result<Self> {
         let token_interceptor = TokenInterceptor::new(
             api_key, match scope {
                 Some(scope) => scope,
                 None => "GIGACHAT_API_PERS".into()
-            }, "https://ngw.devices.sberbank.ru:9443/api/v2/oauth".into()
+            }, "https://ngw.devices.sberbank.ru:9443/api/v2/oauth".into(),
+            network
         ).await?;
         Ok(Self { token_interceptor })
     }
 }
 
+impl Dialect for GigaChatAuth {
+    fn supports_param(&self, name: &str) -> bool {
+        matches!(name, "top_p" | "max_tokens" | "n" | "repetition_penalty")
+    }
+
+    fn uploads_attachments(&self) -> bool {
+        true
+    }
+
+    fn files_api(&self) -> bool {
+        true
+    }
+
+    fn returns_file_references(&self) -> bool {
+        true
+    }
+
+    fn model_capabilities(&self, model: &UpstreamModel) -> ModelCapabilities {
+        if model.type_.as_deref() == Some("embedder") {
+            return ModelCapabilities {
+                tools: Some(false),
+                streaming: Some(false),
+                vision: Some(false),
+                ..Default::default()
+            };
+        }
+
+        // Поддержку изображений `/models` не сообщает, её задают в разделе `models` конфигурации
+        ModelCapabilities {
+            tools: Some(true),
+            streaming: Some(true),
+            ..Default::default()
+        }
+    }
+}
+
+#[async_trait]
 impl AuthProvider for GigaChatAuth {
     fn with_auth(&self, req: RequestBuilder) -> RequestBuilder {
         req.header("Authorization", format!("Bearer {}", self.token_interceptor.get_token()))
     }
+
+    async fn reauthorize(&self, rejected: Option<&HeaderValue>) -> bool {
+        let Some(token) = rejected
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer ")) else {
+            return false;
+        };
+        self.token_interceptor.reauthorize(token).await
+    }
+
+    fn token_status(&self) -> Option<TokenStatus> {
+        Some(self.token_interceptor.status())
+    }
 }
 
 // Реализация для Deepseek
@@ -262,6 +1060,26 @@ pub struct DeepseekAuth {
     api_key: Secret<String>,
 }
 
+impl Dialect for DeepseekAuth {
+    fn supports_response_format(&self, format: &ResponseFormat) -> bool {
+        matches!(format, ResponseFormat::Text | ResponseFormat::JsonObject)
+    }
+
+    fn supports_param(&self, name: &str) -> bool {
+        matches!(name, "top_p" | "max_tokens" | "stop" | "presence_penalty" | "frequency_penalty" | "logprobs")
+    }
+
+    fn model_capabilities(&self, model: &UpstreamModel) -> ModelCapabilities {
+        ModelCapabilities {
+            tools: Some(model.id != "deepseek-reasoner"),
+            streaming: Some(true),
+            vision: Some(false),
+            ..Default::default()
+        }
+    }
+}
+
+#[async_trait]
 impl AuthProvider for DeepseekAuth {
     
     fn with_auth(&self, req: RequestBuilder) -> RequestBuilder {
@@ -274,10 +1092,11 @@ impl GenericLLMService<GigaChatAuth> {
     pub async fn create(config: &ModelData) -> anyhow::Result<Self> {
         let auth = GigaChatAuth::new(
             config.token.clone(),
-            config.scope.clone()
+            config.scope.clone(),
+            &config.network
         ).await?;
         
-        Self::new(auth, "https://gigachat.devices.sberbank.ru/api/v1").await
+        Self::new(auth, "https://gigachat.devices.sberbank.ru/api/v1", GIGACHAT_EMBEDDING_BATCH_SIZE, &config.network).await
     }
 }
 
@@ -287,6 +1106,6 @@ impl GenericLLMService<DeepseekAuth> {
             api_key: config.token.clone()
         };
         
-        Self::new(auth, "https://api.deepseek.com").await
+        Self::new(auth, "https://api.deepseek.com", EMBEDDING_BATCH_SIZE, &config.network).await
     }
 }
\ No newline at end of file
@@ -0,0 +1,77 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Mutex, RwLock};
+
+use chrono::{DateTime, Utc};
+use ringbuffer::{AllocRingBuffer, RingBuffer};
+use serde::{Deserialize, Serialize};
+
+const USAGE_HISTORY_SIZE: usize = 1000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RequestStatus {
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UsageRecord {
+    pub request_id: String,
+    pub provider: String,
+    pub model: String,
+    pub status: RequestStatus,
+    pub prompt_tokens: i32,
+    pub completion_tokens: i32,
+    pub latency_ms: u64,
+    pub finished_at: DateTime<Utc>,
+}
+
+/// Журнал завершённых запросов: последние записи в памяти и, если задан путь,
+/// построчный JSON в файле.
+pub struct UsageTracker {
+    records: RwLock<AllocRingBuffer<UsageRecord>>,
+    log: Option<Mutex<File>>,
+}
+
+impl UsageTracker {
+    pub fn new(path: Option<PathBuf>) -> anyhow::Result<Self> {
+        let log = match path {
+            Some(path) => Some(Mutex::new(
+                OpenOptions::new().create(true).append(true).open(path)?
+            )),
+            None => None,
+        };
+
+        Ok(Self {
+            records: RwLock::new(AllocRingBuffer::new(USAGE_HISTORY_SIZE)),
+            log,
+        })
+    }
+
+    pub fn record(&self, record: UsageRecord) {
+        if let Some(log) = &self.log {
+            let written = serde_json::to_string(&record)
+                .map_err(anyhow::Error::from)
+                .and_then(|line| Ok(writeln!(log.lock().unwrap(), "{line}")?));
+            if let Err(e) = written {
+                eprintln!("Failed to write usage record: {}", e);
+            }
+        }
+
+        self.records.write().unwrap().enqueue(record);
+    }
+
+    pub fn flush(&self) -> std::io::Result<()> {
+        match &self.log {
+            Some(log) => log.lock().unwrap().sync_all(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn recent(&self) -> Vec<UsageRecord> {
+        self.records.read().unwrap().to_vec()
+    }
+}
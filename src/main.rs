@@ -1,45 +1,434 @@
 use axum::{
-    routing::post,
+    routing::{delete, get, post},
     Router, Json,
-    extract::State,
-    http::StatusCode,
+    extract::{Multipart, Path, Query, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
 };
+use std::process::ExitCode;
 use std::sync::Arc;
-use crate::llm::{provider::{LlmProvider, ServiceChatRequest, ServiceChatResponse, ServiceEmbeddingRequest, ServiceEmbeddingResponse}};
+use std::time::Duration;
+use crate::llm::openai::{OpenAiEmbeddingRequest, OpenAiEmbeddingResponse};
+use crate::llm::provider::{
+    CollectionQueryRequest, CollectionQueryResponse, CollectionUpsertRequest, CollectionUpsertResponse,
+    EncodedEmbeddingResponse, LlmProvider, ServiceChatRequest, ServiceChatResponse, ServiceEmbeddingRequest
+};
+use crate::config::reload::ConfigStatus;
+use crate::llm::catalog::ModelCatalogSnapshot;
+use crate::llm::pool::GatewayStatus;
+use crate::llm::experiments::ExperimentInfo;
+use crate::llm::router::TargetStats;
+use crate::llm::content::{FileUpload, UploadedFile};
+use crate::llm::{requests::{Cancelled, DuplicateRequest}, schema::StructuredOutputError};
+use crate::prompts::PromptTemplateInfo;
+use crate::store::{CollectionInfo, CollectionSettings};
+mod admin;
 mod llm;
 mod config;
+mod grpc;
+mod listeners;
+mod prompts;
+mod shutdown;
+mod store;
+mod ws;
+
+// Код выхода, если к сроку остановки остались незавершённые запросы и их пришлось отменить
+const EXIT_DRAIN_TIMEOUT: u8 = 2;
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+async fn main() -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let tls = config::load_tls();
+    let addrs = listeners::load(tls.as_ref())?;
+
     let service = Arc::new(LlmProvider::new().await?);
+    service.start_catalog_refresh();
+    service.start_experiments();
+    service.start_config_watcher();
     
-    let app = Router::new()
+    let mut app = Router::new()
+        .route("/healthz", get(handle_healthz))
+        .route("/readyz", get(handle_readyz))
+        .route("/status", get(handle_status))
         .route("/chat", post(handle_chat))
+        .route("/requests/{id}/cancel", post(handle_cancel))
+        .route("/models", get(handle_models))
+        .route("/routing", get(handle_routing))
+        .route("/config/status", get(handle_config_status))
+        .route("/experiments", get(handle_experiments))
+        .route("/prompts", get(handle_prompts))
+        .route("/files", get(handle_list_files).post(handle_upload_file))
+        .route("/files/{id}", get(handle_get_file).delete(handle_delete_file))
+        .route("/files/{id}/content", get(handle_file_content))
+        .route("/attachments/{token}", get(handle_attachment))
         .route("/embedding", post(handle_embedding))
-        .with_state(service);
-    
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await?;
-    axum::serve(listener, app).await?;
+        .route("/v1/embeddings", post(handle_openai_embeddings))
+        .route("/ws", get(ws::handle_ws))
+        .route("/collections", get(handle_list_collections))
+        .route("/collections/{name}", post(handle_create_collection).delete(handle_remove_collection))
+        .route("/collections/{name}/documents", post(handle_upsert_documents))
+        .route("/collections/{name}/documents/{id}", delete(handle_delete_document))
+        .route("/collections/{name}/query", post(handle_query_collection));
+    match config::admin_token() {
+        Some(token) => app = app.nest("/admin", admin::router(token)),
+        None => println!("ADMIN_TOKEN переменная окружения не установлена. Административный API отключён."),
+    }
+    let app = app.with_state(service.clone());
     
-    Ok(())
+    let stopping = shutdown::stopping();
+    tokio::spawn(async move {
+        shutdown::signal().await;
+        shutdown::stopping().cancel();
+    });
+
+    let http = listeners::serve(app, addrs, tls.clone(), stopping.clone());
+    let grpc = grpc::serve(service.clone(), config::grpc_address()?, tls, stopping.clone());
+    let servers = async { tokio::try_join!(http, grpc) };
+
+    let timeout = config::shutdown_timeout();
+    let deadline = async {
+        stopping.cancelled().await;
+        println!(
+            "Получен сигнал остановки. Новые соединения не принимаются, запросов в работе: {}",
+            service.inflight_total()
+        );
+        tokio::time::sleep(timeout).await;
+    };
+    tokio::pin!(deadline);
+
+    // Серверы закрываются, когда завершатся их соединения; запросы через WebSocket
+    // и теневые копии учитываются отдельно по реестру запросов
+    let drained = tokio::select! {
+        result = servers => {
+            result?;
+            tokio::select! {
+                _ = service.wait_idle() => true,
+                _ = &mut deadline => false,
+            }
+        },
+        _ = &mut deadline => false,
+    };
+
+    let mut code = ExitCode::SUCCESS;
+    if !drained {
+        println!("Срок остановки {:?} истёк, отменено запросов: {}", timeout, service.cancel_all());
+        // Отменённые запросы успевают записать использование
+        let _ = tokio::time::timeout(Duration::from_secs(5), service.wait_idle()).await;
+        code = ExitCode::from(EXIT_DRAIN_TIMEOUT);
+    }
+
+    if !shutdown::stop_tasks(Duration::from_secs(5)).await {
+        println!("Не все фоновые задачи завершились вовремя");
+    }
+    service.flush();
+    println!("Шлюз остановлен");
+
+    Ok(code)
 }
 
 async fn handle_chat(
     State(service): State<Arc<LlmProvider>>,
-    Json(request): Json<ServiceChatRequest>,
-) -> Result<Json<ServiceChatResponse>, (StatusCode, String)> {
-    service
+    headers: HeaderMap,
+    Json(mut request): Json<ServiceChatRequest>,
+) -> Result<(HeaderMap, Json<ServiceChatResponse>), (StatusCode, String)> {
+    let request_id = headers
+        .get("x-request-id")
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    request.request_id = Some(request_id.clone());
+    if request.client_key.is_none() {
+        request.client_key = headers
+            .get("x-client-key")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+    }
+
+    let response = service
         .chat(request).await
+        .map_err(|e| if e.is::<Cancelled>() {
+            // 499 Client Closed Request, как у nginx
+            (StatusCode::from_u16(499).unwrap(), e.to_string())
+        } else if e.is::<StructuredOutputError>() {
+            (StatusCode::UNPROCESSABLE_ENTITY, e.to_string())
+        } else if e.is::<DuplicateRequest>() {
+            (StatusCode::CONFLICT, e.to_string())
+        } else {
+            (StatusCode::BAD_REQUEST, e.to_string())
+        })?;
+
+    let mut headers = HeaderMap::new();
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        headers.insert("x-request-id", value);
+    }
+    if response.semantic_cache_hit {
+        headers.insert("x-semantic-cache", HeaderValue::from_static("hit"));
+    }
+    for (name, value) in [
+        ("x-llm-provider", Some(&response.provider)),
+        ("x-llm-model", Some(&response.model)),
+        ("x-llm-route", response.route.as_ref()),
+    ] {
+        if let Some(value) = value.and_then(|value| HeaderValue::from_str(value).ok()) {
+            headers.insert(name, value);
+        }
+    }
+    if !response.dropped_params.is_empty() {
+        if let Ok(value) = HeaderValue::from_str(&response.dropped_params.join(", ")) {
+            headers.insert("x-dropped-params", value);
+        }
+    }
+
+    Ok((headers, Json(response)))
+}
+
+// Клиент отменяет только свои запросы, начатые с тем же `x-client-key`;
+// любой запрос отменяется через /admin/requests/{id}/cancel
+async fn handle_cancel(
+    State(service): State<Arc<LlmProvider>>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> StatusCode {
+    let Some(client_key) = headers.get("x-client-key").and_then(|value| value.to_str().ok()) else {
+        return StatusCode::UNAUTHORIZED;
+    };
+
+    if service.cancel_owned(&id, client_key) {
+        StatusCode::ACCEPTED
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+async fn handle_prompts(
+    State(service): State<Arc<LlmProvider>>,
+) -> Json<Vec<PromptTemplateInfo>> {
+    Json(service.prompts())
+}
+
+async fn handle_models(
+    State(service): State<Arc<LlmProvider>>,
+) -> Json<ModelCatalogSnapshot> {
+    Json(service.models().await)
+}
+
+async fn handle_routing(
+    State(service): State<Arc<LlmProvider>>,
+) -> Json<Vec<TargetStats>> {
+    Json(service.routing_stats())
+}
+
+// Процесс жив и обрабатывает запросы
+async fn handle_healthz() -> &'static str {
+    "ok"
+}
+
+async fn handle_readyz(
+    State(service): State<Arc<LlmProvider>>,
+) -> (StatusCode, String) {
+    let problems = service.readiness();
+    if problems.is_empty() {
+        (StatusCode::OK, "ok".to_string())
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, problems.join("\n"))
+    }
+}
+
+// Без ошибок провайдеров; проверка провайдеров запросом и полные ошибки - в /admin/status
+async fn handle_status(
+    State(service): State<Arc<LlmProvider>>,
+) -> Json<GatewayStatus> {
+    Json(service.status(false).await.redacted())
+}
+
+async fn handle_config_status(
+    State(service): State<Arc<LlmProvider>>,
+) -> Json<ConfigStatus> {
+    Json(service.config_status())
+}
+
+async fn handle_experiments(
+    State(service): State<Arc<LlmProvider>>,
+) -> Json<Vec<ExperimentInfo>> {
+    Json(service.experiments())
+}
+
+async fn handle_upload_file(
+    State(service): State<Arc<LlmProvider>>,
+    mut multipart: Multipart,
+) -> Result<Json<UploadedFile>, (StatusCode, String)> {
+    let mut provider = None;
+    let mut file = None;
+    while let Some(field) = multipart.next_field().await.map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))? {
+        match field.name() {
+            Some("provider") => provider = Some(field.text().await.map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?),
+            Some("file") => {
+                let filename = field.file_name().unwrap_or("file").to_string();
+                let content_type = field.content_type().unwrap_or("application/octet-stream").to_string();
+                let bytes = field.bytes().await.map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?.to_vec();
+                file = Some(FileUpload { filename, content_type, bytes });
+            },
+            _ => {},
+        }
+    }
+
+    let (Some(provider), Some(file)) = (provider, file) else {
+        return Err((StatusCode::BAD_REQUEST, "Нужны поля provider и file".to_string()));
+    };
+
+    service
+        .upload_file(&provider, file).await
+        .map(Json)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))
+}
+
+#[derive(serde::Deserialize)]
+struct FileQuery {
+    provider: String,
+}
+
+async fn handle_list_files(
+    State(service): State<Arc<LlmProvider>>,
+    Query(query): Query<FileQuery>,
+) -> Result<Json<Vec<UploadedFile>>, (StatusCode, String)> {
+    service
+        .files(&query.provider).await
+        .map(Json)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))
+}
+
+async fn handle_get_file(
+    State(service): State<Arc<LlmProvider>>,
+    Path(id): Path<String>,
+    Query(query): Query<FileQuery>,
+) -> Result<Json<UploadedFile>, (StatusCode, String)> {
+    service
+        .file(&query.provider, &id).await
         .map(Json)
         .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))
 }
 
+async fn handle_file_content(
+    State(service): State<Arc<LlmProvider>>,
+    Path(id): Path<String>,
+    Query(query): Query<FileQuery>,
+) -> Result<(HeaderMap, Vec<u8>), (StatusCode, String)> {
+    let file = service
+        .file_content(&query.provider, &id).await
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+
+    Ok((content_type_header(&file.content_type), file.bytes))
+}
+
+async fn handle_delete_file(
+    State(service): State<Arc<LlmProvider>>,
+    Path(id): Path<String>,
+    Query(query): Query<FileQuery>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    match service.delete_file(&query.provider, &id).await {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Err((StatusCode::NOT_FOUND, format!("Файл - {} - не найден", id))),
+        Err(e) => Err((StatusCode::BAD_REQUEST, e.to_string())),
+    }
+}
+
+async fn handle_attachment(
+    State(service): State<Arc<LlmProvider>>,
+    Path(token): Path<String>,
+) -> Result<(HeaderMap, Vec<u8>), StatusCode> {
+    let (content_type, bytes) = service.attachment(&token).ok_or(StatusCode::NOT_FOUND)?;
+    Ok((content_type_header(&content_type), bytes))
+}
+
+fn content_type_header(value: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    if let Ok(value) = HeaderValue::from_str(value) {
+        headers.insert(header::CONTENT_TYPE, value);
+    }
+    headers
+}
+
 async fn handle_embedding(
     State(service): State<Arc<LlmProvider>>,
     Json(request): Json<ServiceEmbeddingRequest>,
-) -> Result<Json<ServiceEmbeddingResponse>, (StatusCode, String)> {
+) -> Result<Json<EncodedEmbeddingResponse>, (StatusCode, String)> {
+    let format = request.options.encoding_format;
+    service
+        .embedding(request).await
+        .map(|response| Json(response.encode(format)))
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))
+}
+
+async fn handle_openai_embeddings(
+    State(service): State<Arc<LlmProvider>>,
+    Json(request): Json<OpenAiEmbeddingRequest>,
+) -> Result<Json<OpenAiEmbeddingResponse>, (StatusCode, String)> {
+    let model = request.model.clone();
+    let request = ServiceEmbeddingRequest::try_from(request)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    let options = request.options.clone();
+
     service
         .embedding(request).await
+        .map(|response| Json(OpenAiEmbeddingResponse::new(model, response, &options)))
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))
+}
+
+async fn handle_list_collections(
+    State(service): State<Arc<LlmProvider>>,
+) -> Json<Vec<CollectionInfo>> {
+    Json(service.collections())
+}
+
+async fn handle_create_collection(
+    State(service): State<Arc<LlmProvider>>,
+    Path(name): Path<String>,
+    Json(settings): Json<CollectionSettings>,
+) -> Result<Json<CollectionInfo>, (StatusCode, String)> {
+    service
+        .create_collection(&name, settings).await
+        .map(Json)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))
+}
+
+async fn handle_remove_collection(
+    State(service): State<Arc<LlmProvider>>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    match service.remove_collection(&name).await {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Err((StatusCode::NOT_FOUND, format!("Коллекция - {} - не найдена", name))),
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    }
+}
+
+async fn handle_upsert_documents(
+    State(service): State<Arc<LlmProvider>>,
+    Path(name): Path<String>,
+    Json(request): Json<CollectionUpsertRequest>,
+) -> Result<Json<CollectionUpsertResponse>, (StatusCode, String)> {
+    service
+        .upsert_documents(&name, request).await
+        .map(Json)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))
+}
+
+async fn handle_delete_document(
+    State(service): State<Arc<LlmProvider>>,
+    Path((name, id)): Path<(String, String)>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    match service.delete_document(&name, &id).await {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Err((StatusCode::NOT_FOUND, format!("Документ - {} - не найден", id))),
+        Err(e) => Err((StatusCode::BAD_REQUEST, e.to_string())),
+    }
+}
+
+async fn handle_query_collection(
+    State(service): State<Arc<LlmProvider>>,
+    Path(name): Path<String>,
+    Json(request): Json<CollectionQueryRequest>,
+) -> Result<Json<CollectionQueryResponse>, (StatusCode, String)> {
+    service
+        .query_collection(&name, request).await
         .map(Json)
         .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))
 }
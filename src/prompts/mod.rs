@@ -0,0 +1,277 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::llm::ChatMessage;
+use crate::shutdown;
+
+#[derive(Clone, Debug, Deserialize)]
+struct TemplateMessage {
+    role: String,
+    content: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct PromptTemplate {
+    #[serde(default)]
+    pub name: String,
+    pub version: u32,
+    #[serde(default)]
+    pub provider: Option<String>,
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    #[serde(default)]
+    defaults: HashMap<String, String>,
+    messages: Vec<TemplateMessage>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PromptTemplateInfo {
+    pub name: String,
+    pub version: u32,
+    pub provider: Option<String>,
+    pub model: Option<String>,
+    pub temperature: Option<f32>,
+    pub variables: BTreeSet<String>,
+}
+
+impl PromptTemplate {
+    pub fn variables(&self) -> BTreeSet<String> {
+        self.messages
+            .iter()
+            .flat_map(|message| placeholders(&message.content))
+            .collect()
+    }
+
+    pub fn render(&self, variables: &HashMap<String, String>) -> anyhow::Result<Vec<ChatMessage>> {
+        let missing = self.variables()
+            .into_iter()
+            .filter(|name| !variables.contains_key(name) && !self.defaults.contains_key(name))
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Не заданы переменные шаблона {}@{}: {}",
+                self.name, self.version, missing.join(", ")
+            ));
+        }
+
+        Ok(self.messages
+            .iter()
+            .map(|message| {
+                let content = substitute(&message.content, |name| {
+                    variables.get(name).or_else(|| self.defaults.get(name)).cloned().unwrap_or_default()
+                });
+                ChatMessage::text(&message.role, content)
+            })
+            .collect())
+    }
+
+    fn info(&self) -> PromptTemplateInfo {
+        PromptTemplateInfo {
+            name: self.name.clone(),
+            version: self.version,
+            provider: self.provider.clone(),
+            model: self.model.clone(),
+            temperature: self.temperature,
+            variables: self.variables(),
+        }
+    }
+}
+
+fn placeholders(content: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    substitute(content, |name| {
+        names.push(name.to_string());
+        String::new()
+    });
+    names
+}
+
+// Заменяет каждое вхождение `{{ name }}` результатом `value(name)`
+fn substitute(content: &str, mut value: impl FnMut(&str) -> String) -> String {
+    let mut result = String::with_capacity(content.len());
+    let mut rest = content;
+
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start + 2..].find("}}") else {
+            break;
+        };
+        result.push_str(&rest[..start]);
+        result.push_str(&value(rest[start + 2..start + 2 + end].trim()));
+        rest = &rest[start + 2 + end + 2..];
+    }
+    result.push_str(rest);
+
+    result
+}
+
+/// Шаблоны промптов из директории `PROMPTS_PATH`: по одному TOML-файлу на версию.
+/// Ссылка `name@version` выбирает конкретную версию, `name` — последнюю.
+pub struct PromptRegistry {
+    dir: PathBuf,
+    templates: RwLock<HashMap<String, BTreeMap<u32, Arc<PromptTemplate>>>>,
+}
+
+impl PromptRegistry {
+    pub fn new(dir: PathBuf) -> Self {
+        if let Err(e) = fs::create_dir_all(&dir) {
+            println!("Директория {} не может быть создана: {}", dir.display(), e);
+        }
+
+        let registry = Self { dir, templates: RwLock::new(HashMap::new()) };
+        if let Err(e) = registry.load() {
+            eprintln!("Failed to load prompts: {}", e);
+        }
+        registry
+    }
+
+    pub fn load(&self) -> anyhow::Result<()> {
+        let mut templates = HashMap::<String, BTreeMap<u32, Arc<PromptTemplate>>>::new();
+
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("toml") {
+                continue;
+            }
+
+            let template = fs::read_to_string(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|content| Ok(toml::from_str::<PromptTemplate>(&content)?));
+            let mut template = match template {
+                Ok(template) => template,
+                Err(e) => {
+                    eprintln!("Failed to load prompt {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+
+            if template.name.is_empty() {
+                let stem = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or_default();
+                template.name = stem.split('@').next().unwrap_or_default().to_string();
+            }
+            templates
+                .entry(template.name.clone())
+                .or_default()
+                .insert(template.version, Arc::new(template));
+        }
+
+        *self.templates.write().unwrap() = templates;
+        Ok(())
+    }
+
+    pub fn start_watcher(self: &Arc<Self>) {
+        let registry = Arc::downgrade(self);
+
+        shutdown::spawn(async move {
+            while !shutdown::sleep(Duration::from_secs(30)).await {
+                let Some(registry) = registry.upgrade() else {
+                    break;
+                };
+                if let Err(e) = registry.load() {
+                    eprintln!("Failed to reload prompts: {}", e);
+                }
+            }
+        });
+    }
+
+    pub fn get(&self, reference: &str) -> anyhow::Result<Arc<PromptTemplate>> {
+        let (name, version) = match reference.split_once('@') {
+            Some((name, version)) => (name, Some(version.parse::<u32>()?)),
+            None => (reference, None),
+        };
+
+        let templates = self.templates.read().unwrap();
+        let versions = templates.get(name);
+        let template = match version {
+            Some(version) => versions.and_then(|versions| versions.get(&version)),
+            None => versions.and_then(|versions| versions.values().next_back()),
+        };
+
+        template
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Шаблон - {} - не найден", reference))
+    }
+
+    pub fn list(&self) -> Vec<PromptTemplateInfo> {
+        let mut templates = self.templates
+            .read()
+            .unwrap()
+            .values()
+            .flat_map(|versions| versions.values().map(|template| template.info()))
+            .collect::<Vec<_>>();
+        templates.sort_by(|a, b| a.name.cmp(&b.name).then(a.version.cmp(&b.version)));
+        templates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn template() -> PromptTemplate {
+        toml::from_str(r#"
+            name = "summary"
+            version = 2
+
+            [defaults]
+            style = "кратко"
+
+            [[messages]]
+            role = "system"
+            content = "Отвечай {{style}}."
+
+            [[messages]]
+            role = "user"
+            content = "Перескажи {{ text }} для {{text}}"
+        "#).unwrap()
+    }
+
+    fn vars(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn substitutes_trimmed_names() {
+        let result = substitute("{{a}} и {{  b  }}, снова {{a}}", |name| name.to_uppercase());
+        assert_eq!(result, "A и B, снова A");
+    }
+
+    #[test]
+    fn leaves_unclosed_and_plain_text_alone() {
+        assert_eq!(substitute("без переменных", |_| unreachable!()), "без переменных");
+        assert_eq!(substitute("{{a}} и {{b", |_| "x".into()), "x и {{b");
+    }
+
+    #[test]
+    fn substituted_values_are_not_expanded_again() {
+        assert_eq!(substitute("{{a}}", |_| "{{a}}".into()), "{{a}}");
+    }
+
+    #[test]
+    fn collects_variables_from_all_messages() {
+        assert_eq!(template().variables().into_iter().collect::<Vec<_>>(), ["style", "text"]);
+    }
+
+    #[test]
+    fn renders_with_values_and_defaults() {
+        let messages = template().render(&vars(&[("text", "статью")])).unwrap();
+        let contents = messages.iter().map(|message| message.text_content().unwrap()).collect::<Vec<_>>();
+        assert_eq!(contents, ["Отвечай кратко.", "Перескажи статью для статью"]);
+        assert_eq!(messages[1].role, "user");
+
+        let messages = template().render(&vars(&[("text", "статью"), ("style", "подробно")])).unwrap();
+        assert_eq!(messages[0].text_content().unwrap(), "Отвечай подробно.");
+    }
+
+    #[test]
+    fn fails_on_missing_variables() {
+        let error = template().render(&HashMap::new()).unwrap_err().to_string();
+        assert!(error.contains("summary@2") && error.contains("text"), "{error}");
+    }
+}
@@ -0,0 +1,78 @@
+use std::future::Future;
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+
+// Получен сигнал остановки: серверы перестают принимать соединения, запросы в работе доделываются
+static STOPPING: LazyLock<CancellationToken> = LazyLock::new(CancellationToken::new);
+
+// Фоновые задачи процесса: наблюдатели за файлами, обновление токенов и каталога моделей.
+// Останавливаются после запросов, чтобы те не остались, например, без обновлённого токена
+static BACKGROUND: LazyLock<CancellationToken> = LazyLock::new(CancellationToken::new);
+static TASKS: LazyLock<TaskTracker> = LazyLock::new(TaskTracker::new);
+
+pub fn stopping() -> CancellationToken {
+    STOPPING.clone()
+}
+
+pub fn is_stopping() -> bool {
+    STOPPING.is_cancelled()
+}
+
+pub async fn cancelled() {
+    BACKGROUND.cancelled().await
+}
+
+pub fn spawn<F>(task: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    TASKS.spawn(task);
+}
+
+// Пауза фоновой задачи, которую прерывает остановка; возвращает true, если пора завершаться
+pub async fn sleep(duration: Duration) -> bool {
+    tokio::select! {
+        _ = BACKGROUND.cancelled() => true,
+        _ = tokio::time::sleep(duration) => false,
+    }
+}
+
+// Завершается по SIGTERM или Ctrl+C
+pub async fn signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            println!("Не удалось подписаться на Ctrl+C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            },
+            Err(e) => {
+                println!("Не удалось подписаться на SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            },
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+// Останавливает фоновые задачи; возвращает false, если какие-то не успели завершиться
+pub async fn stop_tasks(timeout: Duration) -> bool {
+    BACKGROUND.cancel();
+    TASKS.close();
+    tokio::time::timeout(timeout, TASKS.wait()).await.is_ok()
+}
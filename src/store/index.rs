@@ -0,0 +1,333 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashSet};
+
+use crate::store::Metric;
+
+const HNSW_M: usize = 16;
+const HNSW_EF_CONSTRUCTION: usize = 100;
+const HNSW_EF_SEARCH: usize = 64;
+
+pub trait VectorIndex: Send + Sync {
+    fn insert(&mut self, slot: usize, vector: Vec<f32>);
+    fn remove(&mut self, slot: usize);
+    fn vector(&self, slot: usize) -> Option<&[f32]>;
+    fn search(&self, query: &[f32], k: usize, accept: &dyn Fn(usize) -> bool) -> Vec<(usize, f32)>;
+}
+
+// Точный поиск полным перебором
+
+pub struct FlatIndex {
+    metric: Metric,
+    vectors: Vec<Option<Vec<f32>>>,
+}
+
+impl FlatIndex {
+    pub fn new(metric: Metric) -> Self {
+        Self { metric, vectors: Vec::new() }
+    }
+}
+
+impl VectorIndex for FlatIndex {
+    fn insert(&mut self, slot: usize, vector: Vec<f32>) {
+        if self.vectors.len() <= slot {
+            self.vectors.resize(slot + 1, None);
+        }
+        self.vectors[slot] = Some(vector);
+    }
+
+    fn remove(&mut self, slot: usize) {
+        if let Some(vector) = self.vectors.get_mut(slot) {
+            *vector = None;
+        }
+    }
+
+    fn vector(&self, slot: usize) -> Option<&[f32]> {
+        self.vectors.get(slot)?.as_deref()
+    }
+
+    fn search(&self, query: &[f32], k: usize, accept: &dyn Fn(usize) -> bool) -> Vec<(usize, f32)> {
+        let mut hits = self.vectors
+            .iter()
+            .enumerate()
+            .filter_map(|(slot, vector)| Some((slot, vector.as_deref()?)))
+            .filter(|(slot, _)| accept(*slot))
+            .map(|(slot, vector)| (slot, self.metric.score(query, vector)))
+            .collect::<Vec<_>>();
+
+        hits.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+        hits.truncate(k);
+        hits
+    }
+}
+
+// Приближённый поиск по графу HNSW
+
+#[derive(Clone, Copy, PartialEq)]
+struct Scored(f32, usize);
+
+impl Eq for Scored {}
+
+impl PartialOrd for Scored {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scored {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0).then(self.1.cmp(&other.1))
+    }
+}
+
+pub struct HnswIndex {
+    metric: Metric,
+    level_mult: f64,
+    vectors: Vec<Vec<f32>>,
+    deleted: Vec<bool>,
+    neighbors: Vec<Vec<Vec<usize>>>,
+    entry: Option<usize>,
+    max_level: usize,
+}
+
+impl HnswIndex {
+    pub fn new(metric: Metric) -> Self {
+        Self {
+            metric,
+            level_mult: 1.0 / (HNSW_M as f64).ln(),
+            vectors: Vec::new(),
+            deleted: Vec::new(),
+            neighbors: Vec::new(),
+            entry: None,
+            max_level: 0,
+        }
+    }
+
+    fn level_for(&self, slot: usize) -> usize {
+        // splitmix64: детерминированный, но равномерно распределённый уровень узла
+        let mut x = (slot as u64).wrapping_add(0x9E37_79B9_7F4A_7C15);
+        x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        x ^= x >> 31;
+
+        let uniform = (x >> 11) as f64 / (1u64 << 53) as f64;
+        (-(1.0 - uniform).ln() * self.level_mult).floor() as usize
+    }
+
+    fn score(&self, query: &[f32], slot: usize) -> f32 {
+        self.metric.score(query, &self.vectors[slot])
+    }
+
+    fn search_layer(&self, query: &[f32], entry: usize, ef: usize, layer: usize) -> Vec<Scored> {
+        let mut visited = HashSet::from([entry]);
+        let first = Scored(self.score(query, entry), entry);
+        let mut candidates = BinaryHeap::from([first]);
+        let mut results = BinaryHeap::from([Reverse(first)]);
+
+        while let Some(candidate) = candidates.pop() {
+            let worst = results.peek().map(|Reverse(worst)| worst.0).unwrap_or(f32::MIN);
+            if candidate.0 < worst && results.len() >= ef {
+                break;
+            }
+
+            for &neighbor in &self.neighbors[candidate.1][layer] {
+                if !visited.insert(neighbor) {
+                    continue;
+                }
+
+                let scored = Scored(self.score(query, neighbor), neighbor);
+                let worst = results.peek().map(|Reverse(worst)| worst.0).unwrap_or(f32::MIN);
+                if results.len() < ef || scored.0 > worst {
+                    candidates.push(scored);
+                    results.push(Reverse(scored));
+                    if results.len() > ef {
+                        results.pop();
+                    }
+                }
+            }
+        }
+
+        let mut results = results.into_iter().map(|Reverse(scored)| scored).collect::<Vec<_>>();
+        results.sort_by(|a, b| b.cmp(a));
+        results
+    }
+
+    fn descend(&self, query: &[f32], mut entry: usize, from: usize, to: usize) -> usize {
+        for layer in (to..=from).rev() {
+            if let Some(best) = self.search_layer(query, entry, 1, layer).first() {
+                entry = best.1;
+            }
+        }
+        entry
+    }
+
+    fn prune(&mut self, slot: usize, layer: usize, max_connections: usize) {
+        if self.neighbors[slot][layer].len() <= max_connections {
+            return;
+        }
+
+        let vector = &self.vectors[slot];
+        let mut scored = self.neighbors[slot][layer]
+            .iter()
+            .map(|&neighbor| Scored(self.metric.score(vector, &self.vectors[neighbor]), neighbor))
+            .collect::<Vec<_>>();
+        scored.sort_by(|a, b| b.cmp(a));
+        scored.truncate(max_connections);
+
+        self.neighbors[slot][layer] = scored.into_iter().map(|scored| scored.1).collect();
+    }
+}
+
+impl VectorIndex for HnswIndex {
+    fn insert(&mut self, slot: usize, vector: Vec<f32>) {
+        assert_eq!(slot, self.vectors.len(), "HNSW slots must be appended in order");
+
+        let level = self.level_for(slot);
+        self.vectors.push(vector);
+        self.deleted.push(false);
+        self.neighbors.push(vec![Vec::new(); level + 1]);
+
+        let Some(mut entry) = self.entry else {
+            self.entry = Some(slot);
+            self.max_level = level;
+            return;
+        };
+
+        let query = self.vectors[slot].clone();
+        if level < self.max_level {
+            entry = self.descend(&query, entry, self.max_level, level + 1);
+        }
+
+        for layer in (0..=level.min(self.max_level)).rev() {
+            let candidates = self.search_layer(&query, entry, HNSW_EF_CONSTRUCTION, layer);
+            let max_connections = if layer == 0 { HNSW_M * 2 } else { HNSW_M };
+
+            for candidate in candidates.iter().take(HNSW_M) {
+                self.neighbors[slot][layer].push(candidate.1);
+                self.neighbors[candidate.1][layer].push(slot);
+                self.prune(candidate.1, layer, max_connections);
+            }
+
+            if let Some(best) = candidates.first() {
+                entry = best.1;
+            }
+        }
+
+        if level > self.max_level {
+            self.entry = Some(slot);
+            self.max_level = level;
+        }
+    }
+
+    fn remove(&mut self, slot: usize) {
+        // Узел остаётся в графе для навигации, но исключается из выдачи
+        if let Some(deleted) = self.deleted.get_mut(slot) {
+            *deleted = true;
+        }
+    }
+
+    fn vector(&self, slot: usize) -> Option<&[f32]> {
+        match self.deleted.get(slot) {
+            Some(false) => Some(&self.vectors[slot]),
+            _ => None,
+        }
+    }
+
+    fn search(&self, query: &[f32], k: usize, accept: &dyn Fn(usize) -> bool) -> Vec<(usize, f32)> {
+        let Some(entry) = self.entry else {
+            return Vec::new();
+        };
+
+        let entry = match self.max_level {
+            0 => entry,
+            max_level => self.descend(query, entry, max_level, 1),
+        };
+
+        // Фильтры и удалённые узлы сокращают выдачу, поэтому окно поиска расширяется
+        let mut ef = HNSW_EF_SEARCH.max(k);
+        loop {
+            let hits = self.search_layer(query, entry, ef, 0)
+                .into_iter()
+                .filter(|scored| !self.deleted[scored.1] && accept(scored.1))
+                .take(k)
+                .map(|scored| (scored.1, scored.0))
+                .collect::<Vec<_>>();
+
+            if hits.len() >= k || ef >= self.vectors.len() {
+                return hits;
+            }
+            ef *= 2;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIMENSIONS: usize = 16;
+
+    // Детерминированные псевдослучайные векторы (xorshift), чтобы тест был воспроизводимым
+    fn vectors(count: usize, mut seed: u64) -> Vec<Vec<f32>> {
+        let mut next = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            (seed >> 11) as f32 / (1u64 << 53) as f32 * 2.0 - 1.0
+        };
+        (0..count).map(|_| (0..DIMENSIONS).map(|_| next()).collect()).collect()
+    }
+
+    fn build(index: &mut dyn VectorIndex, vectors: &[Vec<f32>]) {
+        for (slot, vector) in vectors.iter().enumerate() {
+            index.insert(slot, vector.clone());
+        }
+    }
+
+    fn recall(metric: Metric) -> f64 {
+        let data = vectors(2_000, 0x5EED);
+        let (mut flat, mut hnsw) = (FlatIndex::new(metric), HnswIndex::new(metric));
+        build(&mut flat, &data);
+        build(&mut hnsw, &data);
+
+        let k = 10;
+        let queries = vectors(50, 0xC0FFEE);
+        let found = queries.iter().map(|query| {
+            let exact = flat.search(query, k, &|_| true);
+            let approximate = hnsw.search(query, k, &|_| true);
+            exact.iter().filter(|hit| approximate.iter().any(|other| other.0 == hit.0)).count()
+        }).sum::<usize>();
+
+        found as f64 / (queries.len() * k) as f64
+    }
+
+    #[test]
+    fn hnsw_recall_matches_flat_search() {
+        for metric in [Metric::Cosine, Metric::Dot] {
+            let recall = recall(metric);
+            assert!(recall >= 0.9, "recall {recall} для {metric:?}");
+        }
+    }
+
+    #[test]
+    fn search_skips_removed_and_rejected_slots() {
+        let data = vectors(300, 42);
+        let mut hnsw = HnswIndex::new(Metric::Cosine);
+        build(&mut hnsw, &data);
+
+        let query = &data[7];
+        assert_eq!(hnsw.search(query, 1, &|_| true)[0].0, 7);
+
+        hnsw.remove(7);
+        assert!(hnsw.vector(7).is_none());
+        let hits = hnsw.search(query, 5, &|slot| slot % 2 == 0);
+        assert_eq!(hits.len(), 5);
+        assert!(hits.iter().all(|hit| hit.0 != 7 && hit.0 % 2 == 0));
+        assert!(hits.windows(2).all(|pair| pair[0].1 >= pair[1].1));
+    }
+
+    #[test]
+    fn empty_index_returns_nothing() {
+        let hnsw = HnswIndex::new(Metric::Dot);
+        assert!(hnsw.search(&[1.0; DIMENSIONS], 3, &|_| true).is_empty());
+    }
+}
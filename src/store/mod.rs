@@ -0,0 +1,367 @@
+pub mod index;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+use tokio::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::llm::cache::cosine_similarity;
+use crate::store::index::{FlatIndex, HnswIndex, VectorIndex};
+
+// Индекс перестраивается, когда удалённых слотов больше, чем живых, но не раньше этого порога
+const COMPACT_THRESHOLD: usize = 1024;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+    #[default]
+    Cosine,
+    Dot,
+}
+
+impl Metric {
+    pub fn score(&self, a: &[f32], b: &[f32]) -> f32 {
+        match self {
+            Metric::Cosine => cosine_similarity(a, b),
+            Metric::Dot => a.iter().zip(b).map(|(x, y)| x * y).sum(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IndexKind {
+    #[default]
+    Flat,
+    Hnsw,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CollectionSettings {
+    pub provider: String,
+    pub model: String,
+    #[serde(default)]
+    pub metric: Metric,
+    #[serde(default)]
+    pub index: IndexKind,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Document {
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(default)]
+    pub metadata: HashMap<String, Value>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SearchHit {
+    pub id: String,
+    pub score: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    pub metadata: HashMap<String, Value>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct CollectionInfo {
+    pub name: String,
+    #[serde(flatten)]
+    pub settings: CollectionSettings,
+    pub dimension: Option<usize>,
+    pub documents: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredDocument {
+    #[serde(flatten)]
+    document: Document,
+    vector: Vec<f32>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredCollection {
+    settings: CollectionSettings,
+    documents: Vec<StoredDocument>,
+}
+
+pub struct Collection {
+    pub settings: CollectionSettings,
+    dimension: Option<usize>,
+    documents: Vec<Option<Document>>,
+    slots: HashMap<String, usize>,
+    index: Box<dyn VectorIndex>,
+}
+
+impl Collection {
+    fn new(settings: CollectionSettings) -> Self {
+        let index: Box<dyn VectorIndex> = match settings.index {
+            IndexKind::Flat => Box::new(FlatIndex::new(settings.metric)),
+            IndexKind::Hnsw => Box::new(HnswIndex::new(settings.metric)),
+        };
+
+        Self {
+            settings,
+            dimension: None,
+            documents: Vec::new(),
+            slots: HashMap::new(),
+            index,
+        }
+    }
+
+    fn check_dimension(&self, vector: &[f32]) -> anyhow::Result<()> {
+        match self.dimension {
+            Some(dimension) if dimension != vector.len() => Err(anyhow::anyhow!(
+                "Vector dimension {} does not match collection dimension {}",
+                vector.len(), dimension
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    // Все векторы проверяются до изменения коллекции, поэтому пакет применяется целиком или не применяется
+    pub fn upsert_batch(&mut self, documents: Vec<(Document, Vec<f32>)>) -> anyhow::Result<()> {
+        let mut dimension = self.dimension;
+        for (document, vector) in &documents {
+            if vector.is_empty() {
+                return Err(anyhow::anyhow!("Document {} has an empty vector", document.id));
+            }
+            match dimension {
+                Some(dimension) if dimension != vector.len() => return Err(anyhow::anyhow!(
+                    "Vector dimension {} of document {} does not match collection dimension {}",
+                    vector.len(), document.id, dimension
+                )),
+                _ => dimension = Some(vector.len()),
+            }
+        }
+        self.dimension = dimension;
+
+        for (document, vector) in documents {
+            self.remove_slot(&document.id);
+
+            let slot = self.documents.len();
+            self.index.insert(slot, vector);
+            self.slots.insert(document.id.clone(), slot);
+            self.documents.push(Some(document));
+        }
+        self.compact();
+
+        Ok(())
+    }
+
+    pub fn delete(&mut self, id: &str) -> bool {
+        let deleted = self.remove_slot(id);
+        if deleted {
+            self.compact();
+        }
+        deleted
+    }
+
+    fn remove_slot(&mut self, id: &str) -> bool {
+        match self.slots.remove(id) {
+            Some(slot) => {
+                self.documents[slot] = None;
+                self.index.remove(slot);
+                true
+            },
+            None => false,
+        }
+    }
+
+    // Слоты удалённых документов остаются в индексе, пока коллекция не перестроена
+    fn compact(&mut self) {
+        let live = self.slots.len();
+        let removed = self.documents.len() - live;
+        if removed <= live.max(COMPACT_THRESHOLD) {
+            return;
+        }
+
+        let mut compacted = Self::new(self.settings.clone());
+        compacted.dimension = self.dimension;
+        for (slot, document) in std::mem::take(&mut self.documents).into_iter().enumerate() {
+            let (Some(document), Some(vector)) = (document, self.index.vector(slot)) else {
+                continue;
+            };
+            let vector = vector.to_vec();
+            let slot = compacted.documents.len();
+            compacted.index.insert(slot, vector);
+            compacted.slots.insert(document.id.clone(), slot);
+            compacted.documents.push(Some(document));
+        }
+        *self = compacted;
+    }
+
+    pub fn search(
+        &self,
+        query: &[f32],
+        top_k: usize,
+        filter: &HashMap<String, Value>,
+    ) -> anyhow::Result<Vec<SearchHit>> {
+        self.check_dimension(query)?;
+
+        let accept = |slot: usize| {
+            self.documents[slot]
+                .as_ref()
+                .is_some_and(|document| filter
+                    .iter()
+                    .all(|(key, value)| document.metadata.get(key) == Some(value))
+                )
+        };
+
+        Ok(self.index
+            .search(query, top_k, &accept)
+            .into_iter()
+            .filter_map(|(slot, score)| {
+                let document = self.documents[slot].as_ref()?;
+                Some(SearchHit {
+                    id: document.id.clone(),
+                    score,
+                    text: document.text.clone(),
+                    metadata: document.metadata.clone(),
+                })
+            })
+            .collect())
+    }
+
+    fn info(&self, name: &str) -> CollectionInfo {
+        CollectionInfo {
+            name: name.to_string(),
+            settings: self.settings.clone(),
+            dimension: self.dimension,
+            documents: self.slots.len(),
+        }
+    }
+
+    fn to_stored(&self) -> StoredCollection {
+        StoredCollection {
+            settings: self.settings.clone(),
+            documents: self.documents
+                .iter()
+                .enumerate()
+                .filter_map(|(slot, document)| Some(StoredDocument {
+                    document: document.clone()?,
+                    vector: self.index.vector(slot)?.to_vec(),
+                }))
+                .collect(),
+        }
+    }
+
+    fn from_stored(stored: StoredCollection) -> anyhow::Result<Self> {
+        let mut collection = Self::new(stored.settings);
+        collection.upsert_batch(stored.documents
+            .into_iter()
+            .map(|StoredDocument { document, vector }| (document, vector))
+            .collect())?;
+        Ok(collection)
+    }
+}
+
+/// Именованные коллекции векторов, каждая хранится отдельным JSON-файлом в `root`.
+pub struct VectorStore {
+    root: PathBuf,
+    collections: RwLock<HashMap<String, Arc<RwLock<Collection>>>>,
+    // Файлы коллекций пишутся по одному, чтобы старый снимок не перезаписал новый
+    writing: Mutex<()>,
+}
+
+impl VectorStore {
+    pub fn open(root: PathBuf) -> anyhow::Result<Self> {
+        fs::create_dir_all(&root)?;
+
+        let mut collections = HashMap::new();
+        for entry in fs::read_dir(&root)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+                continue;
+            }
+            let Some(name) = path.file_stem().and_then(|name| name.to_str()) else {
+                continue;
+            };
+
+            match serde_json::from_slice::<StoredCollection>(&fs::read(&path)?)
+                .map_err(anyhow::Error::from)
+                .and_then(Collection::from_stored)
+            {
+                Ok(collection) => {
+                    collections.insert(name.to_string(), Arc::new(RwLock::new(collection)));
+                },
+                Err(e) => eprintln!("Failed to load collection {}: {}", name, e),
+            }
+        }
+
+        Ok(Self { root, collections: RwLock::new(collections), writing: Mutex::new(()) })
+    }
+
+    pub fn list(&self) -> Vec<CollectionInfo> {
+        let mut collections = self.collections
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, collection)| collection.read().unwrap().info(name))
+            .collect::<Vec<_>>();
+        collections.sort_by(|a, b| a.name.cmp(&b.name));
+        collections
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<RwLock<Collection>>> {
+        self.collections.read().unwrap().get(name).cloned()
+    }
+
+    pub async fn create(&self, name: &str, settings: CollectionSettings) -> anyhow::Result<CollectionInfo> {
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return Err(anyhow::anyhow!("Invalid collection name: {}", name));
+        }
+
+        let _writing = self.writing.lock().await;
+        if self.collections.read().unwrap().contains_key(name) {
+            return Err(anyhow::anyhow!("Collection {} already exists", name));
+        }
+
+        let collection = Collection::new(settings);
+        self.write(name, collection.to_stored()).await?;
+        let info = collection.info(name);
+        self.collections.write().unwrap().insert(name.to_string(), Arc::new(RwLock::new(collection)));
+
+        Ok(info)
+    }
+
+    pub async fn remove(&self, name: &str) -> anyhow::Result<bool> {
+        let _writing = self.writing.lock().await;
+        let removed = self.collections.write().unwrap().remove(name);
+        match removed {
+            Some(_) => {
+                tokio::fs::remove_file(self.root.join(format!("{name}.json"))).await?;
+                Ok(true)
+            },
+            None => Ok(false),
+        }
+    }
+
+    // Снимок берётся под коротким чтением, а сериализация и запись идут вне асинхронных потоков
+    pub async fn persist(&self, name: &str) -> anyhow::Result<()> {
+        let _writing = self.writing.lock().await;
+        let Some(collection) = self.get(name) else {
+            // Коллекцию удалили, пока шла запись в неё
+            return Ok(());
+        };
+        let stored = collection.read().unwrap().to_stored();
+        self.write(name, stored).await
+    }
+
+    async fn write(&self, name: &str, stored: StoredCollection) -> anyhow::Result<()> {
+        let path = self.root.join(format!("{name}.json"));
+        let tmp = self.root.join(format!("{name}.json.tmp"));
+
+        tokio::task::spawn_blocking(move || -> anyhow::Result<()> {
+            fs::write(&tmp, serde_json::to_vec(&stored)?)?;
+            fs::rename(tmp, path)?;
+            Ok(())
+        }).await?
+    }
+}
@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use axum::{
+    extract::{ws::{Message, WebSocket, WebSocketUpgrade}, State},
+    response::Response,
+};
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio::task::AbortHandle;
+
+use crate::llm::provider::{LlmProvider, ServiceChatRequest};
+use crate::llm::ChatEvent;
+
+// Один сокет обслуживает несколько генераций одновременно,
+// каждая адресуется идентификатором, который выбирает клиент
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    Chat {
+        id: String,
+        #[serde(flatten)]
+        request: Box<ServiceChatRequest>,
+    },
+    Cancel {
+        id: String,
+    },
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum SessionEvent {
+    Error { message: String },
+    Cancelled,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum ServerEvent {
+    Chat(ChatEvent),
+    Session(SessionEvent),
+}
+
+#[derive(Serialize)]
+struct ServerMessage {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    #[serde(flatten)]
+    event: ServerEvent,
+}
+
+impl ServerMessage {
+    fn error(id: Option<String>, message: impl ToString) -> Self {
+        Self { id, event: ServerEvent::Session(SessionEvent::Error { message: message.to_string() }) }
+    }
+}
+
+pub async fn handle_ws(
+    ws: WebSocketUpgrade,
+    State(provider): State<Arc<LlmProvider>>,
+) -> Response {
+    ws.on_upgrade(move |socket| session(socket, provider))
+}
+
+async fn session(socket: WebSocket, provider: Arc<LlmProvider>) {
+    let (mut sink, mut stream) = socket.split();
+    let (tx, mut rx) = mpsc::unbounded_channel::<ServerMessage>();
+
+    let writer = tokio::spawn(async move {
+        while let Some(message) = rx.recv().await {
+            let Ok(text) = serde_json::to_string(&message) else {
+                continue;
+            };
+            if sink.send(Message::Text(text.into())).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut generations = HashMap::<String, AbortHandle>::new();
+    while let Some(Ok(message)) = stream.next().await {
+        let text = match message {
+            Message::Text(text) => text,
+            Message::Close(_) => break,
+            _ => continue,
+        };
+
+        generations.retain(|_, generation| !generation.is_finished());
+        match serde_json::from_str::<ClientMessage>(&text) {
+            Ok(ClientMessage::Chat { id, request }) => {
+                if generations.contains_key(&id) {
+                    let _ = tx.send(ServerMessage::error(Some(id), "Генерация с таким id уже выполняется"));
+                    continue;
+                }
+
+                let generation = tokio::spawn(generate(provider.clone(), id.clone(), *request, tx.clone()));
+                generations.insert(id, generation.abort_handle());
+            },
+            Ok(ClientMessage::Cancel { id }) => {
+                if let Some(generation) = generations.remove(&id) {
+                    generation.abort();
+                    let _ = tx.send(ServerMessage { id: Some(id), event: ServerEvent::Session(SessionEvent::Cancelled) });
+                }
+            },
+            Err(e) => {
+                let _ = tx.send(ServerMessage::error(None, e));
+            },
+        }
+    }
+
+    generations.values().for_each(AbortHandle::abort);
+    writer.abort();
+}
+
+async fn generate(
+    provider: Arc<LlmProvider>,
+    id: String,
+    request: ServiceChatRequest,
+    tx: UnboundedSender<ServerMessage>,
+) {
+    let mut stream = match provider.chat_stream(request).await.map_err(|e| e.to_string()) {
+        Ok(stream) => stream,
+        Err(e) => {
+            let _ = tx.send(ServerMessage::error(Some(id), e));
+            return;
+        }
+    };
+
+    while let Some(event) = stream.next().await {
+        let message = match event {
+            Ok(event) => ServerMessage { id: Some(id.clone()), event: ServerEvent::Chat(event) },
+            Err(e) => ServerMessage::error(Some(id.clone()), e),
+        };
+        if tx.send(message).is_err() {
+            break;
+        }
+    }
+}